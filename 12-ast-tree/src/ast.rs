@@ -0,0 +1,178 @@
+use std::fmt;
+
+pub type Span = std::ops::Range<usize>;
+
+// Unit names with their powers, in the order they were written: `m/s^2` is
+// `[("m", 1), ("s", -2)]`.
+pub type Unit = Vec<(String, i32)>;
+
+// Where a span starts, both 1-based. Nodes that don't come from source are at 0:0.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    pub position: Position,
+}
+
+// Spans are ignored when comparing, so trees built by hand or rewritten by the
+// optimizer compare equal to parsed ones.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind,
+            span,
+            position: Position::default(),
+        }
+    }
+}
+
+// Nodes that don't come from source, like generated ones, get an empty span.
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Expr::new(kind, 0..0)
+    }
+}
+
+// The digits a number literal was written with, so exact evaluation isn't limited to
+// what an `f64` can hold. Like spans they are ignored when comparing, and numbers that
+// don't come from source have none.
+#[derive(Debug, Clone, Default)]
+pub struct Digits(pub Option<String>);
+
+impl PartialEq for Digits {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Num(f64, Digits),
+    Quantity(f64, Unit),
+    Bool(bool),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Negative(Box<Expr>),
+    Invert(Box<Expr>),
+    Not(Box<Expr>),
+
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mult(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Mod(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+
+    Eq(Box<Expr>, Box<Expr>),
+    NotEq(Box<Expr>, Box<Expr>),
+    Less(Box<Expr>, Box<Expr>),
+    LessEq(Box<Expr>, Box<Expr>),
+    Greater(Box<Expr>, Box<Expr>),
+    GreaterEq(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Lambda(Vec<String>, Box<Expr>),
+    List(Vec<Expr>),
+}
+
+impl ExprKind {
+    pub fn num(x: f64) -> Self {
+        ExprKind::Num(x, Digits::default())
+    }
+
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            ExprKind::Num(_, _)
+            | ExprKind::Quantity(_, _)
+            | ExprKind::Bool(_)
+            | ExprKind::Var(_) => {
+                vec![]
+            }
+            ExprKind::Call(_, args) | ExprKind::List(args) => args.iter().collect(),
+            ExprKind::Negative(x)
+            | ExprKind::Invert(x)
+            | ExprKind::Not(x)
+            | ExprKind::Lambda(_, x) => vec![x],
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mult(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::NotEq(lhs, rhs)
+            | ExprKind::Less(lhs, rhs)
+            | ExprKind::LessEq(lhs, rhs)
+            | ExprKind::Greater(lhs, rhs)
+            | ExprKind::GreaterEq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs) => vec![lhs, rhs],
+            ExprKind::If(cond, then, otherwise) => vec![cond, then, otherwise],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            ExprKind::Num(_, _)
+            | ExprKind::Quantity(_, _)
+            | ExprKind::Bool(_)
+            | ExprKind::Var(_) => {
+                vec![]
+            }
+            ExprKind::Call(_, args) | ExprKind::List(args) => args.iter_mut().collect(),
+            ExprKind::Negative(x)
+            | ExprKind::Invert(x)
+            | ExprKind::Not(x)
+            | ExprKind::Lambda(_, x) => vec![x],
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mult(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::NotEq(lhs, rhs)
+            | ExprKind::Less(lhs, rhs)
+            | ExprKind::LessEq(lhs, rhs)
+            | ExprKind::Greater(lhs, rhs)
+            | ExprKind::GreaterEq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs) => vec![lhs, rhs],
+            ExprKind::If(cond, then, otherwise) => vec![cond, then, otherwise],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Let(String, Expr),
+    Fn(String, Vec<String>, Expr),
+    Print(Expr),
+}
+
+// Scripts that only print don't need a final expression, so `result` is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    pub result: Option<Expr>,
+}
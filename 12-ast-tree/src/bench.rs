@@ -0,0 +1,169 @@
+use crate::ast::{Expr, ExprKind};
+use crate::eval::{self, Env, Value};
+use crate::vm::{self, Vm};
+use std::time::{Duration, Instant};
+
+const VARIABLES: [&str; 3] = ["x", "y", "rate"];
+
+// A small linear congruential generator keeps generated expressions reproducible
+// without pulling in a random number crate.
+pub fn generate(seed: &mut u64, depth: u32) -> Expr {
+    *seed = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+
+    let choice = (*seed >> 33) % if depth == 0 { 2 } else { 8 };
+    let mut sub = || Box::new(generate(seed, depth - 1));
+
+    let kind = match choice {
+        0 => ExprKind::num((*seed >> 40) as f64 / 8.0),
+        1 => ExprKind::Var(VARIABLES[(*seed >> 40) as usize % VARIABLES.len()].to_string()),
+        2 => ExprKind::Negative(sub()),
+        3 => ExprKind::Invert(sub()),
+        4 => ExprKind::Add(sub(), sub()),
+        5 => ExprKind::Sub(sub(), sub()),
+        6 => ExprKind::Mult(sub(), sub()),
+        _ => ExprKind::Div(sub(), sub()),
+    };
+
+    kind.into()
+}
+
+fn count_nodes(expr: &Expr) -> usize {
+    match &expr.kind {
+        ExprKind::Num(_, _) | ExprKind::Quantity(_, _) | ExprKind::Bool(_) | ExprKind::Var(_) => 1,
+        ExprKind::Call(_, args) | ExprKind::List(args) => {
+            1 + args.iter().map(count_nodes).sum::<usize>()
+        }
+        ExprKind::Negative(x) | ExprKind::Invert(x) | ExprKind::Not(x) | ExprKind::Lambda(_, x) => {
+            1 + count_nodes(x)
+        }
+        ExprKind::Add(lhs, rhs)
+        | ExprKind::Sub(lhs, rhs)
+        | ExprKind::Mult(lhs, rhs)
+        | ExprKind::Div(lhs, rhs)
+        | ExprKind::Mod(lhs, rhs)
+        | ExprKind::Pow(lhs, rhs)
+        | ExprKind::Eq(lhs, rhs)
+        | ExprKind::NotEq(lhs, rhs)
+        | ExprKind::Less(lhs, rhs)
+        | ExprKind::LessEq(lhs, rhs)
+        | ExprKind::Greater(lhs, rhs)
+        | ExprKind::GreaterEq(lhs, rhs)
+        | ExprKind::And(lhs, rhs)
+        | ExprKind::Or(lhs, rhs) => 1 + count_nodes(lhs) + count_nodes(rhs),
+        ExprKind::If(cond, then, otherwise) => {
+            1 + count_nodes(cond) + count_nodes(then) + count_nodes(otherwise)
+        }
+    }
+}
+
+fn inputs(i: usize) -> [Value; 3] {
+    [
+        Value::Num(i as f64),
+        Value::Num(1.0 + i as f64 / 2.0),
+        Value::Num(0.25),
+    ]
+}
+
+fn report(name: &str, elapsed: Duration, iterations: usize) {
+    println!(
+        "{:<10} {:>10.3?} total, {:>10.3?} per evaluation",
+        name,
+        elapsed,
+        Duration::from_secs_f64(elapsed.as_secs_f64() / iterations as f64)
+    );
+}
+
+pub fn run(depth: u32, iterations: usize, seed: u64) -> bool {
+    // Every expression of depth 0 is a single node, so none would be big enough.
+    if depth == 0 || iterations == 0 {
+        eprintln!("error: --depth and --iterations must be at least 1");
+        return false;
+    }
+
+    let mut seed = seed;
+    let expr = (0..)
+        .map(|_| generate(&mut seed, depth))
+        .find(|expr| count_nodes(expr) > 1 << (depth / 2))
+        .unwrap();
+
+    let nodes = count_nodes(&expr);
+    let program = crate::ast::Program {
+        stmts: vec![],
+        result: Some(expr),
+    };
+
+    let chunk = match vm::compile(&program) {
+        Ok(chunk) => chunk,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return false;
+        }
+    };
+
+    println!(
+        "{} nodes, {} bytes of bytecode, {} iterations",
+        nodes,
+        chunk.code.len(),
+        iterations
+    );
+
+    let start = Instant::now();
+    let mut tree_results = Vec::with_capacity(iterations);
+
+    for i in 0..iterations {
+        let env = VARIABLES
+            .iter()
+            .zip(inputs(i))
+            .fold(Env::new(), |env, (name, value)| env.bind(name, value));
+
+        tree_results.push(eval::eval_program(&program, &env, &mut |_| {}).map_err(|err| err.error));
+    }
+
+    let tree = start.elapsed();
+
+    let order = chunk
+        .inputs
+        .iter()
+        .map(|(name, _)| VARIABLES.iter().position(|v| v == name).unwrap())
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut vm_results = Vec::with_capacity(iterations);
+    let mut machine = Vm::new();
+
+    for i in 0..iterations {
+        let values = inputs(i);
+        let args = order.iter().map(|&j| values[j].clone()).collect::<Vec<_>>();
+
+        vm_results.push(machine.run(&chunk, &args, &mut |_| {}));
+    }
+
+    let vm = start.elapsed();
+
+    report("tree walk", tree, iterations);
+    report("vm", vm, iterations);
+    println!("speedup    {:.2}x", tree.as_secs_f64() / vm.as_secs_f64());
+
+    let same = |(a, b): (&Result<Option<Value>, _>, &Result<Option<Value>, _>)| match (a, b) {
+        (Ok(Some(Value::Num(a))), Ok(Some(Value::Num(b)))) => {
+            a.to_bits() == b.to_bits() || a.is_nan() && b.is_nan()
+        }
+        _ => a == b,
+    };
+
+    if !tree_results.iter().zip(&vm_results).all(same) {
+        eprintln!("error: tree walking and vm results differ");
+        return false;
+    }
+
+    true
+}
+
+#[test]
+fn test_bench_rejects_empty_runs() {
+    assert!(!run(0, 10, 42));
+    assert!(!run(4, 0, 42));
+    assert!(run(1, 3, 42));
+}
@@ -0,0 +1,12 @@
+// The language server on its own, for editors that expect a separate executable. It
+// is the same server as `ast-tree lsp`.
+fn main() {
+    match ast_tree::lsp::run() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::f64::consts;
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    apply: fn(&[f64]) -> f64,
+}
+
+impl Builtin {
+    // The caller checks the arity, so `args` always has `self.arity` values.
+    pub fn apply(&self, args: &[f64]) -> f64 {
+        (self.apply)(args)
+    }
+}
+
+pub const BUILTINS: [Builtin; 13] = [
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        apply: |x| x[0].sqrt(),
+    },
+    Builtin {
+        name: "abs",
+        arity: 1,
+        apply: |x| x[0].abs(),
+    },
+    Builtin {
+        name: "min",
+        arity: 2,
+        apply: |x| x[0].min(x[1]),
+    },
+    Builtin {
+        name: "max",
+        arity: 2,
+        apply: |x| x[0].max(x[1]),
+    },
+    Builtin {
+        name: "floor",
+        arity: 1,
+        apply: |x| x[0].floor(),
+    },
+    Builtin {
+        name: "ceil",
+        arity: 1,
+        apply: |x| x[0].ceil(),
+    },
+    Builtin {
+        name: "round",
+        arity: 1,
+        apply: |x| x[0].round(),
+    },
+    Builtin {
+        name: "exp",
+        arity: 1,
+        apply: |x| x[0].exp(),
+    },
+    Builtin {
+        name: "ln",
+        arity: 1,
+        apply: |x| x[0].ln(),
+    },
+    Builtin {
+        name: "log10",
+        arity: 1,
+        apply: |x| x[0].log10(),
+    },
+    Builtin {
+        name: "sin",
+        arity: 1,
+        apply: |x| x[0].sin(),
+    },
+    Builtin {
+        name: "cos",
+        arity: 1,
+        apply: |x| x[0].cos(),
+    },
+    Builtin {
+        name: "tan",
+        arity: 1,
+        apply: |x| x[0].tan(),
+    },
+];
+
+// Built-ins that take lists and functions, with their arities. The evaluator runs
+// them itself, since they call back into the program.
+pub const HIGHER_ORDER: [(&str, usize); 5] = [
+    ("map", 2),
+    ("filter", 2),
+    ("fold", 3),
+    ("apply", 2),
+    ("len", 1),
+];
+
+pub const CONSTANTS: [(&str, f64); 2] = [("pi", consts::PI), ("e", consts::E)];
+
+// User definitions shadow built-ins, so these are only consulted when a name is not
+// bound in the environment.
+pub fn builtin(name: &str) -> Option<(usize, &'static Builtin)> {
+    BUILTINS.iter().enumerate().find(|(_, b)| b.name == name)
+}
+
+pub fn higher_order(name: &str) -> Option<usize> {
+    HIGHER_ORDER
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, arity)| *arity)
+}
+
+pub fn constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter().find(|(n, _)| *n == name).map(|(_, x)| *x)
+}
+
+// The closest built-in function name, for "did you mean" hints on typos.
+pub fn suggest(name: &str) -> Option<&'static str> {
+    BUILTINS
+        .iter()
+        .map(|b| b.name)
+        .chain(HIGHER_ORDER.iter().map(|(n, _)| *n))
+        .map(|b| (distance(name, b), b))
+        .filter(|(d, b)| *d <= 2 && *d < b.len())
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, b)| b)
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let next = (prev + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[test]
+fn test_builtin_lookup() {
+    let (index, sqrt) = builtin("sqrt").unwrap();
+
+    assert_eq!(BUILTINS[index].name, "sqrt");
+    assert_eq!(sqrt.apply(&[9.0]), 3.0);
+    assert_eq!(builtin("max").unwrap().1.apply(&[1.0, 2.0]), 2.0);
+    assert!(builtin("nope").is_none());
+    assert_eq!(constant("pi"), Some(consts::PI));
+}
+
+#[test]
+fn test_suggest() {
+    assert_eq!(suggest("sqr"), Some("sqrt"));
+    assert_eq!(suggest("flor"), Some("floor"));
+    assert_eq!(suggest("cso"), Some("cos"));
+    assert_eq!(suggest("area"), None);
+    assert_eq!(suggest("folds"), Some("fold"));
+}
@@ -0,0 +1,531 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::builtins;
+use crate::check::{Type, Types};
+use crate::eval::{check_arity, EvalError, MAX_CALL_DEPTH};
+
+// Everything a generated program needs besides its own code. Booleans are doubles
+// that are 0 or 1, and runtime errors exit with the message and position the
+// interpreter would report. Nothing is `static`, so unused parts don't cause warnings.
+const HEADER: &str = "#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+";
+
+const RUNTIME: &str = r#"int depth = 0;
+
+void fail(const char *message, int line, int column) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n --> %s:%d:%d\n", message, source, line, column);
+    exit(1);
+}
+
+/* Prints like Rust's `{}` does: the fewest digits that read back as the same
+   double, without an exponent. */
+void print_number(double x) {
+    char buf[32], digits[20];
+    int precision, exponent, count = 0, i;
+    char *c;
+
+    if (isnan(x)) {
+        puts("NaN");
+        return;
+    }
+
+    if (isinf(x)) {
+        puts(x < 0 ? "-inf" : "inf");
+        return;
+    }
+
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(buf, sizeof buf, "%.*e", precision, x);
+
+        if (strtod(buf, NULL) == x) {
+            break;
+        }
+    }
+
+    for (c = buf; *c != 'e'; c++) {
+        if (*c >= '0' && *c <= '9') {
+            digits[count++] = *c;
+        }
+    }
+
+    exponent = atoi(c + 1);
+
+    if (signbit(x)) {
+        putchar('-');
+    }
+
+    if (exponent < 0) {
+        printf("0.");
+
+        for (i = 0; i < -exponent - 1; i++) {
+            putchar('0');
+        }
+
+        printf("%.*s\n", count, digits);
+    } else if (exponent + 1 >= count) {
+        printf("%.*s", count, digits);
+
+        for (i = count; i <= exponent; i++) {
+            putchar('0');
+        }
+
+        putchar('\n');
+    } else {
+        printf("%.*s.%.*s\n", exponent + 1, digits, count - exponent - 1, digits + exponent + 1);
+    }
+}
+
+void print_bool(double b) {
+    puts(b != 0 ? "true" : "false");
+}
+"#;
+
+#[derive(Clone)]
+enum Binding {
+    Value(String),
+    Function(String, usize),
+}
+
+// Lowers a program to C one operation at a time, so operands are evaluated left to
+// right and errors come in the same order as in the interpreter. Every name gets a
+// numbered C name, since `let` can shadow and C can't.
+struct Generator<'a> {
+    scope: Vec<(&'a str, Binding)>,
+    names: usize,
+    temps: usize,
+    indent: usize,
+    globals: String,
+    functions: String,
+    body: String,
+}
+
+fn c_string(text: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => out += &format!("\\{}", c),
+            '\n' => out += "\\n",
+            c if c.is_ascii_control() => out += &format!("\\{:03o}", c as u8),
+            c => out.push(c),
+        }
+    }
+
+    out + "\""
+}
+
+fn literal(x: f64) -> String {
+    if x.is_nan() {
+        "NAN".to_string()
+    } else if x.is_infinite() {
+        format!("{}INFINITY", if x < 0.0 { "-" } else { "" })
+    } else if x.is_sign_negative() {
+        format!("({:?})", x)
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+fn c_builtin(name: &str) -> &'static str {
+    match name {
+        "abs" => "fabs",
+        "min" => "fmin",
+        "max" => "fmax",
+        "ln" => "log",
+        "sqrt" => "sqrt",
+        "floor" => "floor",
+        "ceil" => "ceil",
+        "round" => "round",
+        "exp" => "exp",
+        "log10" => "log10",
+        "sin" => "sin",
+        "cos" => "cos",
+        _ => "tan",
+    }
+}
+
+// The program has to type-check, so `types` knows which printed values are booleans.
+pub fn to_c(program: &Program, types: &Types, filename: &str) -> Result<String, EvalError> {
+    let mut gen = Generator {
+        scope: Vec::new(),
+        names: 0,
+        temps: 0,
+        indent: 1,
+        globals: String::new(),
+        functions: String::new(),
+        body: String::new(),
+    };
+    let mut printed = types.printed.iter();
+
+    for stmt in &program.stmts {
+        match stmt {
+            Stmt::Let(name, value) => {
+                let value = gen.expr(value)?;
+                let global = gen.name("v", name);
+
+                gen.globals += &format!("double {};\n", global);
+                gen.line(format!("{} = {};", global, value));
+                gen.scope.push((name, Binding::Value(global)));
+            }
+            Stmt::Fn(name, params, body) => gen.function(name, params, body)?,
+            Stmt::Print(value) => {
+                let value = gen.expr(value)?;
+                gen.print(value, printed.next() == Some(&Type::Bool));
+            }
+        }
+    }
+
+    if let Some(result) = &program.result {
+        let result = gen.expr(result)?;
+        gen.print(result, types.result == Some(Type::Bool));
+    }
+
+    Ok(format!(
+        "{}\nconst char *source = {};\n\n{}\n{}{}\nint main(void) {{\n{}    return 0;\n}}\n",
+        HEADER,
+        c_string(filename),
+        RUNTIME,
+        gen.globals,
+        gen.functions,
+        gen.body
+    ))
+}
+
+impl<'a> Generator<'a> {
+    fn name(&mut self, prefix: &str, name: &str) -> String {
+        self.names += 1;
+        format!("{}{}_{}", prefix, self.names, name)
+    }
+
+    fn line(&mut self, text: String) {
+        self.body += &"    ".repeat(self.indent);
+        self.body += &text;
+        self.body += "\n";
+    }
+
+    fn temp(&mut self, value: String) -> String {
+        let temp = format!("t{}", self.temps);
+
+        self.temps += 1;
+        self.line(format!("double {} = {};", temp, value));
+        temp
+    }
+
+    fn fail_if(&mut self, cond: String, err: EvalError, expr: &Expr) {
+        let position = expr.position;
+
+        self.line(format!(
+            "if ({}) fail({}, {}, {});",
+            cond,
+            c_string(&err.to_string()),
+            position.line,
+            position.column
+        ));
+    }
+
+    fn print(&mut self, value: String, boolean: bool) {
+        let print = if boolean {
+            "print_bool"
+        } else {
+            "print_number"
+        };
+        self.line(format!("{}({});", print, value));
+    }
+
+    // The code for `expr` goes in its own block, which assigns its value to `temp`.
+    fn block(&mut self, temp: &str, expr: &'a Expr) -> Result<(), EvalError> {
+        self.indent += 1;
+        let value = self.expr(expr)?;
+        self.line(format!("{} = {};", temp, value));
+        self.indent -= 1;
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        name: &'a str,
+        params: &'a [String],
+        body: &'a Expr,
+    ) -> Result<(), EvalError> {
+        let function = self.name("f", name);
+        let outer = self.scope.len();
+        let params_c = params
+            .iter()
+            .map(|param| format!("double p_{}", param))
+            .collect::<Vec<_>>();
+
+        self.scope
+            .push((name, Binding::Function(function.clone(), params.len())));
+        self.scope.extend(
+            params
+                .iter()
+                .map(|param| (param.as_str(), Binding::Value(format!("p_{}", param)))),
+        );
+
+        let body_outer = std::mem::take(&mut self.body);
+        let value = self.expr(body)?;
+        let body = std::mem::replace(&mut self.body, body_outer);
+
+        self.functions += &format!(
+            "double {}({}) {{\n{}    return {};\n}}\n\n",
+            function,
+            if params.is_empty() {
+                "void".to_string()
+            } else {
+                params_c.join(", ")
+            },
+            body,
+            value
+        );
+
+        self.scope.truncate(outer);
+        self.scope
+            .push((name, Binding::Function(function, params.len())));
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, binding)| binding)
+    }
+
+    fn binary(&mut self, lhs: &'a Expr, op: &str, rhs: &'a Expr) -> Result<String, EvalError> {
+        let (lhs, rhs) = (self.expr(lhs)?, self.expr(rhs)?);
+        Ok(self.temp(format!("{} {} {}", lhs, op, rhs)))
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Result<String, EvalError> {
+        let value = match &expr.kind {
+            ExprKind::Num(x, _) => return Ok(literal(*x)),
+            ExprKind::Quantity(_, _) => {
+                let err = EvalError::Units("units are not supported in C output".to_string());
+                return Err(err);
+            }
+            ExprKind::Lambda(_, _) | ExprKind::List(_) => {
+                let err = "lambdas and lists are not supported in C output".to_string();
+                return Err(EvalError::Unsupported(err));
+            }
+            ExprKind::Bool(b) => return Ok(if *b { "1.0" } else { "0.0" }.to_string()),
+            ExprKind::Var(name) => {
+                return match self.lookup(name) {
+                    Some(Binding::Value(name)) => Ok(name.clone()),
+                    Some(Binding::Function(_, _)) => Err(EvalError::NotAValue(name.clone())),
+                    None => builtins::constant(name)
+                        .map(literal)
+                        .ok_or_else(|| EvalError::UndefinedVariable(name.clone())),
+                };
+            }
+            ExprKind::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                match self.lookup(name).cloned() {
+                    Some(Binding::Function(function, arity)) => {
+                        check_arity(name, arity, args.len())?;
+
+                        let err = EvalError::RecursionLimit(name.clone());
+                        self.fail_if(format!("depth >= {}", MAX_CALL_DEPTH), err, expr);
+                        self.line("depth++;".to_string());
+                        let result = self.temp(format!("{}({})", function, args.join(", ")));
+                        self.line("depth--;".to_string());
+
+                        return Ok(result);
+                    }
+                    Some(Binding::Value(_)) => return Err(EvalError::NotAFunction(name.clone())),
+                    None if builtins::higher_order(name).is_some() => {
+                        let err = format!("`{}` is not supported in C output", name);
+                        return Err(EvalError::Unsupported(err));
+                    }
+                    None => {
+                        let (_, builtin) = builtins::builtin(name)
+                            .ok_or_else(|| EvalError::UndefinedFunction(name.clone()))?;
+
+                        check_arity(name, builtin.arity, args.len())?;
+                        format!("{}({})", c_builtin(name), args.join(", "))
+                    }
+                }
+            }
+            ExprKind::Negative(x) => format!("-{}", self.expr(x)?),
+            ExprKind::Invert(x) => {
+                let x = self.expr(x)?;
+                self.fail_if(format!("{} == 0", x), EvalError::DivisionByZero, expr);
+                format!("1.0 / {}", x)
+            }
+            ExprKind::Not(x) => format!("!{}", self.expr(x)?),
+
+            ExprKind::Add(lhs, rhs) => return self.binary(lhs, "+", rhs),
+            ExprKind::Sub(lhs, rhs) => return self.binary(lhs, "-", rhs),
+            ExprKind::Mult(lhs, rhs) => return self.binary(lhs, "*", rhs),
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) => {
+                let (lhs, rhs) = (self.expr(lhs)?, self.expr(rhs)?);
+                self.fail_if(format!("{} == 0", rhs), EvalError::DivisionByZero, expr);
+
+                match expr.kind {
+                    ExprKind::Div(_, _) => format!("{} / {}", lhs, rhs),
+                    _ => format!("fmod({}, {})", lhs, rhs),
+                }
+            }
+            ExprKind::Pow(lhs, rhs) => {
+                let (lhs, rhs) = (self.expr(lhs)?, self.expr(rhs)?);
+                format!("pow({}, {})", lhs, rhs)
+            }
+
+            ExprKind::Eq(lhs, rhs) => return self.binary(lhs, "==", rhs),
+            ExprKind::NotEq(lhs, rhs) => return self.binary(lhs, "!=", rhs),
+            ExprKind::Less(lhs, rhs) => return self.binary(lhs, "<", rhs),
+            ExprKind::LessEq(lhs, rhs) => return self.binary(lhs, "<=", rhs),
+            ExprKind::Greater(lhs, rhs) => return self.binary(lhs, ">", rhs),
+            ExprKind::GreaterEq(lhs, rhs) => return self.binary(lhs, ">=", rhs),
+            ExprKind::And(lhs, rhs) | ExprKind::Or(lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let result = self.temp(lhs);
+                let skip = if let ExprKind::And(_, _) = expr.kind {
+                    ""
+                } else {
+                    "!"
+                };
+
+                self.line(format!("if ({}{}) {{", skip, result));
+                self.block(&result, rhs)?;
+                self.line("}".to_string());
+
+                return Ok(result);
+            }
+
+            ExprKind::If(cond, then, otherwise) => {
+                let cond = self.expr(cond)?;
+                let result = format!("t{}", self.temps);
+
+                self.temps += 1;
+                self.line(format!("double {};", result));
+                self.line(format!("if ({}) {{", cond));
+                self.block(&result, then)?;
+                self.line("} else {".to_string());
+                self.block(&result, otherwise)?;
+                self.line("}".to_string());
+
+                return Ok(result);
+            }
+        };
+
+        Ok(self.temp(value))
+    }
+}
+
+#[cfg(test)]
+static BUILDS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// Compiles `src` with the system C compiler and runs it, returning what it printed,
+// or the first line of its error.
+#[cfg(test)]
+fn run_c(src: &str) -> Result<String, String> {
+    use std::process::Command;
+
+    let program = crate::parser::parse(src).unwrap();
+    let types = crate::check::check_program(&program).unwrap();
+    let code = to_c(&program, &types, "test.x").unwrap();
+
+    let build = BUILDS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let base = std::env::temp_dir().join(format!("ast-tree-{}-{}", std::process::id(), build));
+    let (c_file, exe) = (base.with_extension("c"), base.with_extension("out"));
+
+    std::fs::write(&c_file, code).unwrap();
+
+    // The call depth check is what stops recursion, which the compiler can't tell.
+    let cc = Command::new("cc")
+        .args([
+            "-std=c99",
+            "-Wall",
+            "-Werror",
+            "-Wno-infinite-recursion",
+            "-O2",
+            "-o",
+        ])
+        .arg(&exe)
+        .arg(&c_file)
+        .arg("-lm")
+        .output()
+        .unwrap();
+    assert!(
+        cc.status.success(),
+        "{}",
+        String::from_utf8_lossy(&cc.stderr)
+    );
+
+    let run = Command::new(&exe).output().unwrap();
+    std::fs::remove_file(&c_file).unwrap();
+    std::fs::remove_file(&exe).unwrap();
+
+    let stdout = String::from_utf8(run.stdout).unwrap();
+
+    if run.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8(run.stderr).unwrap();
+        Err(stdout + stderr.lines().next().unwrap())
+    }
+}
+
+#[cfg(test)]
+fn interpret(src: &str) -> Result<String, String> {
+    use crate::eval::{eval_program, Env};
+
+    let mut out = String::new();
+    let result = eval_program(
+        &crate::parser::parse(src).unwrap(),
+        &Env::new(),
+        &mut |value| out += &format!("{}\n", value),
+    );
+
+    match result {
+        Ok(Some(value)) => Ok(out + &format!("{}\n", value)),
+        Ok(None) => Ok(out),
+        Err(err) => Err(out + &format!("error: {}", err)),
+    }
+}
+
+#[test]
+fn test_c_matches_interpreter() {
+    for src in [
+        "1 + ~1234 - 2 / 3",
+        "let rate = 3; let n = 12; print rate; rate * n",
+        "let x = 2; let x = x * x; x + 1",
+        "fn area(w, h) = w * h; area(3, 4) + area(1, 2)",
+        "let k = 2; fn f(x) = x * k; let k = 10; f(3) + k",
+        "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); print fact(5) > 100; fact(20)",
+        "2 ^ 3 ^ 2 % 5 - sqrt(2) * max(3, pi) + ln(e) + round(-2.5) + abs(-0.5)",
+        "print 1e21; print 1e-7; print 0.1 + 0.2; print -0 * 1; print 123.456; 1 / 3",
+        "print 2 ^ 1024; print -(2 ^ 1024); print sqrt(-1); 5e-324",
+        "false && 1 / 0 > 0 || true || 1 / 0 > 0",
+        "fn f(a, b) = a == b || !(a < b); print f(1, 1); f(1, 2)",
+        "let big = if false then 1 else 2; -(if big > 1 then big else 0)",
+        "print 1; print 2 % (1 - 1); 3",
+        "print 7; ~(1 - 1)",
+        "fn f(x) = f(x + 1); f(0)",
+    ] {
+        assert_eq!(run_c(src), interpret(src), "{}", src);
+    }
+}
+
+#[test]
+fn test_c_reports_positions() {
+    assert_eq!(
+        run_c("let x = 0;\nprint 1;\n1 +\n  2 / x"),
+        Err("1\nerror: division by zero".to_string())
+    );
+
+    let program = crate::parser::parse("let x = 0;\n1 +\n  2 / x").unwrap();
+    let types = crate::check::check_program(&program).unwrap();
+    let code = to_c(&program, &types, "a \"b\".x").unwrap();
+
+    assert!(code.contains("const char *source = \"a \\\"b\\\".x\";\n"));
+    assert!(code.contains("fail(\"division by zero\", 3, 3);"));
+}
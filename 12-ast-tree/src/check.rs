@@ -0,0 +1,569 @@
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt};
+use crate::builtins;
+use crate::diagnostics::Diagnostic;
+use crate::eval::EvalError;
+use std::collections::HashMap;
+use std::fmt;
+
+// Every number is an `f64` at runtime, ints are the numbers the checker can prove are
+// whole, and they can be used anywhere a float can. `Unknown` is the type of a
+// recursive call whose result is still being worked out, and it is compatible with
+// everything. Lists and lambdas don't track what they hold, so their items, their
+// parameters and what calling them returns are `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    List,
+    Function,
+    Unknown,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::List => write!(f, "list"),
+            Type::Function => write!(f, "function"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(err: &TypeError) -> Self {
+        Diagnostic {
+            message: err.message.clone(),
+            span: err.span.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Binding {
+    Value(Type),
+    Function(usize),
+}
+
+type Scope<'a> = Vec<(&'a str, Binding)>;
+
+struct Function<'a> {
+    name: &'a str,
+    params: &'a [String],
+    body: &'a Expr,
+    scope: Scope<'a>,
+}
+
+// Functions have no annotations, so a body is checked once for every combination of
+// argument types it is called with.
+#[derive(Default)]
+struct Checker<'a> {
+    functions: Vec<Function<'a>>,
+    instances: HashMap<(usize, Vec<Type>), Type>,
+    errors: Vec<TypeError>,
+}
+
+// The types of the values a program prints and of its result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Types {
+    pub printed: Vec<Type>,
+    pub result: Option<Type>,
+}
+
+pub fn check_program(program: &Program) -> Result<Types, Vec<TypeError>> {
+    let mut checker = Checker::default();
+    let mut scope = Scope::new();
+    let mut printed = Vec::new();
+
+    for stmt in &program.stmts {
+        match stmt {
+            Stmt::Let(name, value) => {
+                let ty = checker.expr(value, &scope);
+                scope.push((name, Binding::Value(ty)));
+            }
+            Stmt::Fn(name, params, body) => {
+                checker.functions.push(Function {
+                    name,
+                    params,
+                    body,
+                    scope: scope.clone(),
+                });
+
+                let index = checker.functions.len() - 1;
+
+                // Checking with unknown arguments reports the errors that don't depend
+                // on how the function is called, even if it never is.
+                checker.instantiate(index, vec![Type::Unknown; params.len()]);
+                scope.push((name, Binding::Function(index)));
+            }
+            Stmt::Print(value) => printed.push(checker.expr(value, &scope)),
+        }
+    }
+
+    let result = program
+        .result
+        .as_ref()
+        .map(|result| checker.expr(result, &scope));
+
+    if checker.errors.is_empty() {
+        Ok(Types { printed, result })
+    } else {
+        Err(checker.errors)
+    }
+}
+
+fn is_number(ty: Type) -> bool {
+    matches!(ty, Type::Int | Type::Float | Type::Unknown)
+}
+
+fn join_numbers(lhs: Type, rhs: Type) -> Type {
+    match (lhs, rhs) {
+        (Type::Int, Type::Int) => Type::Int,
+        (Type::Float | Type::Int, Type::Float | Type::Int) => Type::Float,
+        _ => Type::Unknown,
+    }
+}
+
+fn list(types: &[Type]) -> String {
+    types
+        .iter()
+        .map(Type::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, span: &Span, message: String) {
+        let err = TypeError {
+            span: span.clone(),
+            message,
+        };
+
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    fn expect(&mut self, expr: &Expr, ty: Type, expected: Type) -> Type {
+        let ok = match expected {
+            Type::Int | Type::Float => is_number(ty),
+            _ => ty == expected || ty == Type::Unknown,
+        };
+
+        if !ok {
+            let expected = match expected {
+                Type::Bool => "a boolean",
+                Type::List => "a list",
+                Type::Function => "a function",
+                _ => "a number",
+            };
+
+            self.error(
+                &expr.span,
+                format!("expected {} but found {}", expected, ty),
+            );
+        }
+
+        ty
+    }
+
+    fn number(&mut self, expr: &'a Expr, scope: &Scope<'a>) -> Type {
+        let ty = self.expr(expr, scope);
+        self.expect(expr, ty, Type::Float)
+    }
+
+    fn boolean(&mut self, expr: &'a Expr, scope: &Scope<'a>) -> Type {
+        let ty = self.expr(expr, scope);
+        self.expect(expr, ty, Type::Bool)
+    }
+
+    fn arithmetic(&mut self, lhs: &'a Expr, rhs: &'a Expr, scope: &Scope<'a>) -> Type {
+        let lhs = self.number(lhs, scope);
+        let rhs = self.number(rhs, scope);
+
+        join_numbers(lhs, rhs)
+    }
+
+    fn instantiate(&mut self, index: usize, args: Vec<Type>) -> Type {
+        let key = (index, args);
+
+        if let Some(ty) = self.instances.get(&key) {
+            return *ty;
+        }
+
+        // Recursive calls see `Unknown` until the body has been checked.
+        self.instances.insert(key.clone(), Type::Unknown);
+
+        let function = &self.functions[index];
+        let body = function.body;
+        let mut scope = function.scope.clone();
+
+        scope.push((function.name, Binding::Function(index)));
+        scope.extend(
+            function
+                .params
+                .iter()
+                .zip(&key.1)
+                .map(|(param, ty)| (param.as_str(), Binding::Value(*ty))),
+        );
+
+        let ty = self.expr(body, &scope);
+        self.instances.insert(key, ty);
+        ty
+    }
+
+    // Errors that only show up for these argument types are reported at the call, since
+    // that is where the mistake is.
+    fn call(&mut self, expr: &Expr, name: &str, index: usize, args: Vec<Type>) -> Type {
+        let known = self.errors.len();
+        let ty = self.instantiate(index, args.clone());
+
+        for err in self.errors.split_off(known) {
+            let message = format!("in call to `{}({})`: {}", name, list(&args), err.message);
+            self.error(&expr.span, message);
+        }
+
+        ty
+    }
+
+    // What the functions passed to `map` and friends return isn't known, so neither is
+    // what `fold` and `apply` return.
+    fn higher_order(&mut self, name: &str, args: &[Expr], types: Vec<Type>) -> Type {
+        let (expected, result): (&[Type], _) = match name {
+            "map" | "filter" => (&[Type::List, Type::Function], Type::List),
+            "fold" => (&[Type::List, Type::Unknown, Type::Function], Type::Unknown),
+            "apply" => (&[Type::Function, Type::List], Type::Unknown),
+            _ => (&[Type::List], Type::Int),
+        };
+
+        for ((arg, ty), expected) in args.iter().zip(types).zip(expected) {
+            if *expected != Type::Unknown {
+                self.expect(arg, ty, *expected);
+            }
+        }
+
+        result
+    }
+
+    fn expr(&mut self, expr: &'a Expr, scope: &Scope<'a>) -> Type {
+        let lookup = |name: &str| {
+            scope
+                .iter()
+                .rev()
+                .find(|(n, _)| *n == name)
+                .map(|(_, binding)| *binding)
+        };
+
+        match &expr.kind {
+            ExprKind::Num(x, _) if x.fract() == 0.0 => Type::Int,
+            ExprKind::Num(_, _) | ExprKind::Quantity(_, _) => Type::Float,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Var(name) => match lookup(name) {
+                Some(Binding::Value(ty)) => ty,
+                Some(Binding::Function(_)) => {
+                    self.error(&expr.span, EvalError::NotAValue(name.clone()).to_string());
+                    Type::Unknown
+                }
+                None if builtins::constant(name).is_some() => Type::Float,
+                None => {
+                    let err = EvalError::UndefinedVariable(name.clone());
+                    self.error(&expr.span, err.to_string());
+                    Type::Unknown
+                }
+            },
+            ExprKind::Call(name, args) => {
+                let types = args
+                    .iter()
+                    .map(|arg| self.expr(arg, scope))
+                    .collect::<Vec<_>>();
+
+                let (arity, index) = match lookup(name) {
+                    Some(Binding::Function(index)) => {
+                        (self.functions[index].params.len(), Some(index))
+                    }
+                    // A lambda's parameters aren't known until it is called.
+                    Some(Binding::Value(Type::Function | Type::Unknown)) => return Type::Unknown,
+                    Some(Binding::Value(_)) => {
+                        self.error(
+                            &expr.span,
+                            EvalError::NotAFunction(name.clone()).to_string(),
+                        );
+                        return Type::Unknown;
+                    }
+                    None => match builtins::builtin(name) {
+                        Some((_, builtin)) => (builtin.arity, None),
+                        None => match builtins::higher_order(name) {
+                            Some(arity) => (arity, None),
+                            None => {
+                                let err = EvalError::UndefinedFunction(name.clone());
+                                self.error(&expr.span, err.to_string());
+                                return Type::Unknown;
+                            }
+                        },
+                    },
+                };
+
+                if arity != types.len() {
+                    let err = EvalError::ArityMismatch {
+                        name: name.clone(),
+                        expected: arity,
+                        found: types.len(),
+                    };
+
+                    self.error(&expr.span, err.to_string());
+                    return Type::Unknown;
+                }
+
+                if let Some(index) = index {
+                    return self.call(expr, name, index, types);
+                }
+
+                if builtins::higher_order(name).is_some() {
+                    return self.higher_order(name, args, types);
+                }
+
+                for (arg, ty) in args.iter().zip(&types) {
+                    self.expect(arg, *ty, Type::Float);
+                }
+
+                match name.as_str() {
+                    "floor" | "ceil" | "round" => Type::Int,
+                    "abs" | "min" | "max" => types.into_iter().reduce(join_numbers).unwrap(),
+                    _ => Type::Float,
+                }
+            }
+            ExprKind::Negative(x) => self.number(x, scope),
+            ExprKind::Invert(x) => {
+                self.number(x, scope);
+                Type::Float
+            }
+            ExprKind::Not(x) => {
+                self.boolean(x, scope);
+                Type::Bool
+            }
+
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mult(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs) => self.arithmetic(lhs, rhs, scope),
+            // `2 ^ -1` is not whole, so powers are floats like quotients.
+            ExprKind::Div(lhs, rhs) | ExprKind::Pow(lhs, rhs) => {
+                self.arithmetic(lhs, rhs, scope);
+                Type::Float
+            }
+
+            ExprKind::Eq(lhs, rhs) | ExprKind::NotEq(lhs, rhs) => {
+                let (lhs, rhs) = (self.expr(lhs, scope), self.expr(rhs, scope));
+
+                let comparable = match (lhs, rhs) {
+                    (Type::Function, _) | (_, Type::Function) => false,
+                    (Type::Unknown, _) | (_, Type::Unknown) => true,
+                    (lhs, rhs) => lhs == rhs || is_number(lhs) && is_number(rhs),
+                };
+
+                if !comparable {
+                    self.error(&expr.span, format!("cannot compare {} with {}", lhs, rhs));
+                }
+
+                Type::Bool
+            }
+            ExprKind::Less(lhs, rhs)
+            | ExprKind::LessEq(lhs, rhs)
+            | ExprKind::Greater(lhs, rhs)
+            | ExprKind::GreaterEq(lhs, rhs) => {
+                self.arithmetic(lhs, rhs, scope);
+                Type::Bool
+            }
+            ExprKind::And(lhs, rhs) | ExprKind::Or(lhs, rhs) => {
+                self.boolean(lhs, scope);
+                self.boolean(rhs, scope);
+                Type::Bool
+            }
+
+            ExprKind::Lambda(params, body) => {
+                let mut scope = scope.clone();
+                scope.extend(
+                    params
+                        .iter()
+                        .map(|param| (param.as_str(), Binding::Value(Type::Unknown))),
+                );
+
+                self.expr(body, &scope);
+                Type::Function
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item, scope);
+                }
+
+                Type::List
+            }
+            ExprKind::If(cond, then, otherwise) => {
+                self.boolean(cond, scope);
+
+                match (self.expr(then, scope), self.expr(otherwise, scope)) {
+                    (Type::Unknown, ty) | (ty, Type::Unknown) => ty,
+                    (lhs, rhs) if lhs == rhs => lhs,
+                    (lhs, rhs) if is_number(lhs) && is_number(rhs) => Type::Float,
+                    (lhs, rhs) => {
+                        self.error(
+                            &otherwise.span,
+                            format!("`if` and `else` have different types: {} and {}", lhs, rhs),
+                        );
+                        Type::Unknown
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn check_str(src: &str) -> Result<Type, Vec<(Span, String)>> {
+    check_program(&crate::parser::parse(src).unwrap())
+        .map(|types| types.result.unwrap())
+        .map_err(|errs| errs.into_iter().map(|e| (e.span, e.message)).collect())
+}
+
+#[test]
+fn test_check_number_types() {
+    assert_eq!(check_str("1 + 2 * 3"), Ok(Type::Int));
+    assert_eq!(check_str("1 + 2.5"), Ok(Type::Float));
+    assert_eq!(check_str("6 / 3"), Ok(Type::Float));
+    assert_eq!(check_str("floor(2.5) % 2"), Ok(Type::Int));
+    assert_eq!(check_str("max(1, 2) + pi"), Ok(Type::Float));
+    assert_eq!(check_str("1 < 2 && !false"), Ok(Type::Bool));
+}
+
+#[test]
+fn test_check_if() {
+    assert_eq!(check_str("if true then 1 else 2"), Ok(Type::Int));
+    assert_eq!(check_str("if true then 1 else 2.5"), Ok(Type::Float));
+    assert_eq!(
+        check_str("if 1 then true else 2"),
+        Err(vec![
+            (3..4, "expected a boolean but found int".to_string()),
+            (
+                20..21,
+                "`if` and `else` have different types: bool and int".to_string()
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_check_reports_spans() {
+    assert_eq!(
+        check_str("let x = 1;\nx + true * 2"),
+        Err(vec![(
+            15..19,
+            "expected a number but found bool".to_string()
+        )])
+    );
+    assert_eq!(
+        check_str("1 == true"),
+        Err(vec![(0..9, "cannot compare int with bool".to_string())])
+    );
+    assert_eq!(
+        check_str("y + sqr(2)"),
+        Err(vec![
+            (0..1, "undefined variable `y`".to_string()),
+            (
+                4..10,
+                "undefined function `sqr`, did you mean `sqrt`?".to_string()
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_check_functions() {
+    assert_eq!(
+        check_str("fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); fact(5)"),
+        Ok(Type::Int)
+    );
+    assert_eq!(
+        check_str("fn half(x) = x / 2; fn twice(x) = x + x; twice(1) + twice(half(1))"),
+        Ok(Type::Float)
+    );
+    assert_eq!(
+        check_str("fn not(b) = !b; not(true) || not(1 > 2)"),
+        Ok(Type::Bool)
+    );
+}
+
+#[test]
+fn test_check_function_arguments() {
+    assert_eq!(
+        check_str("fn inc(x) = x + 1;\ninc(1) + inc(true)"),
+        Err(vec![(
+            28..37,
+            "in call to `inc(bool)`: expected a number but found bool".to_string()
+        )])
+    );
+    assert_eq!(
+        check_str("fn f(a, b) = a; f(1)"),
+        Err(vec![(
+            16..20,
+            "`f` takes 2 argument(s) but 1 were given".to_string()
+        )])
+    );
+    assert_eq!(
+        check_str("sqrt(1 > 0)"),
+        Err(vec![(
+            5..10,
+            "expected a number but found bool".to_string()
+        )])
+    );
+}
+
+#[test]
+fn test_check_uncalled_function_bodies() {
+    assert_eq!(
+        check_str("fn f(x) = x + true; 1"),
+        Err(vec![(
+            14..18,
+            "expected a number but found bool".to_string()
+        )])
+    );
+    assert_eq!(check_str("fn f(x) = x; 1"), Ok(Type::Int));
+}
+
+#[test]
+fn test_check_lambdas_and_lists() {
+    assert_eq!(check_str("[1, true]"), Ok(Type::List));
+    assert_eq!(check_str("|x| x + 1"), Ok(Type::Function));
+    assert_eq!(check_str("map([1, 2], |x| x * 2)"), Ok(Type::List));
+    assert_eq!(check_str("len(filter([1], |x| x > 0)) + 1"), Ok(Type::Int));
+    assert_eq!(check_str("let f = |x| x; f(1) + 1"), Ok(Type::Unknown));
+    assert_eq!(
+        check_str("map(|x| x, [1])"),
+        Err(vec![
+            (4..9, "expected a list but found function".to_string()),
+            (11..14, "expected a function but found list".to_string()),
+        ])
+    );
+    assert_eq!(
+        check_str("fold([1], 0)"),
+        Err(vec![(
+            0..12,
+            "`fold` takes 3 argument(s) but 2 were given".to_string()
+        )])
+    );
+    assert_eq!(
+        check_str("(|x| x) == (|x| x)"),
+        Err(vec![(
+            0..18,
+            "cannot compare function with function".to_string()
+        )])
+    );
+}
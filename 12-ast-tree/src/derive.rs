@@ -0,0 +1,460 @@
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt};
+use crate::diagnostics::Diagnostic;
+use crate::eval::check_arity;
+use crate::optimize::optimize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl From<&DiffError> for Diagnostic {
+    fn from(err: &DiffError) -> Self {
+        Diagnostic {
+            message: err.message.clone(),
+            span: err.span.clone(),
+        }
+    }
+}
+
+fn error(expr: &Expr, message: String) -> DiffError {
+    DiffError {
+        span: expr.span.clone(),
+        message,
+    }
+}
+
+#[derive(Clone)]
+enum Binding<'a> {
+    Value(Expr),
+    Function(&'a [String], &'a Expr),
+}
+
+type Scope<'a> = Vec<(&'a str, Binding<'a>)>;
+
+// The derivative of the program's result. Definitions are inlined first, so
+// `let y = x * x; y + 1` is derived as `x * x + 1`. A `let` of `var` itself is kept
+// as a name, which makes the result the derivative at whatever value it was given.
+// Every other free name is a constant.
+pub fn derive(program: &Program, var: &str) -> Result<Expr, DiffError> {
+    let mut scope = Scope::new();
+
+    for stmt in &program.stmts {
+        match stmt {
+            Stmt::Let(name, _) if name == var => {
+                scope.push((name, Binding::Value(ExprKind::Var(name.clone()).into())))
+            }
+            Stmt::Let(name, value) => {
+                let value = inline(value, &scope, &mut Vec::new())?;
+                scope.push((name, Binding::Value(value)));
+            }
+            Stmt::Fn(name, params, body) => scope.push((name, Binding::Function(params, body))),
+            Stmt::Print(_) => (),
+        }
+    }
+
+    let result = match &program.result {
+        Some(result) => result,
+        None => {
+            let end = program.stmts.last().map_or(0, |stmt| match stmt {
+                Stmt::Let(_, expr) | Stmt::Fn(_, _, expr) | Stmt::Print(expr) => expr.span.end,
+            });
+
+            return Err(DiffError {
+                span: end..end,
+                message: "the program has no result to differentiate".to_string(),
+            });
+        }
+    };
+
+    let simplify = |result| {
+        let program = Program {
+            stmts: vec![],
+            result: Some(result),
+        };
+
+        optimize(&program).0.result.unwrap()
+    };
+    let result = simplify(inline(result, &scope, &mut Vec::new())?);
+
+    Ok(simplify(diff(&result, var)?))
+}
+
+// A function body sees the scope it was defined in, plus itself and its parameters.
+// `calls` holds the functions being inlined, by where they are in the scope, so a
+// recursive call is reported instead of expanding forever.
+fn inline(expr: &Expr, scope: &Scope, calls: &mut Vec<usize>) -> Result<Expr, DiffError> {
+    let found = |name: &str| scope.iter().rposition(|(n, _)| *n == name);
+
+    let kind = match &expr.kind {
+        ExprKind::Var(name) => match found(name).map(|i| &scope[i].1) {
+            Some(Binding::Value(value)) => return Ok(value.clone()),
+            _ => expr.kind.clone(),
+        },
+        ExprKind::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| inline(arg, scope, calls))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let function = found(name).and_then(|i| match &scope[i].1 {
+                Binding::Function(params, body) => Some((i, params, body)),
+                Binding::Value(_) => None,
+            });
+
+            // Anything else is a built-in, or an error `diff` reports.
+            let (index, params, body) = match function {
+                Some(function) => function,
+                None => {
+                    let kind = ExprKind::Call(name.clone(), args);
+                    return Ok(Expr::new(kind, expr.span.clone()));
+                }
+            };
+
+            if calls.contains(&index) {
+                return Err(error(
+                    expr,
+                    format!("cannot differentiate recursive function `{}`", name),
+                ));
+            }
+
+            check_arity(name, params.len(), args.len())
+                .map_err(|err| error(expr, err.to_string()))?;
+
+            let mut inner = scope[..=index].to_vec();
+            inner.extend(
+                params
+                    .iter()
+                    .zip(args)
+                    .map(|(param, arg)| (param.as_str(), Binding::Value(arg))),
+            );
+
+            calls.push(index);
+            let body = inline(body, &inner, calls);
+            calls.pop();
+
+            return body;
+        }
+        _ => {
+            let mut kind = expr.kind.clone();
+
+            for child in kind.children_mut() {
+                *child = inline(child, scope, calls)?;
+            }
+
+            kind
+        }
+    };
+
+    Ok(Expr::new(kind, expr.span.clone()))
+}
+
+// Derivatives are built from nodes that drop zero terms and fold numbers as they go,
+// so the optimizer is only left with the finishing touches.
+fn num(x: f64) -> Expr {
+    ExprKind::num(x).into()
+}
+
+fn value(expr: &Expr) -> Option<f64> {
+    match expr.kind {
+        ExprKind::Num(x, _) => Some(x),
+        _ => None,
+    }
+}
+
+fn is(expr: &Expr, x: f64) -> bool {
+    value(expr) == Some(x)
+}
+
+fn add(lhs: Expr, rhs: Expr) -> Expr {
+    match (value(&lhs), value(&rhs)) {
+        (Some(a), Some(b)) => num(a + b),
+        (Some(0.0), _) => rhs,
+        (_, Some(0.0)) => lhs,
+        _ => ExprKind::Add(Box::new(lhs), Box::new(rhs)).into(),
+    }
+}
+
+fn sub(lhs: Expr, rhs: Expr) -> Expr {
+    match (value(&lhs), value(&rhs)) {
+        (Some(a), Some(b)) => num(a - b),
+        (Some(0.0), _) => neg(rhs),
+        (_, Some(0.0)) => lhs,
+        _ => ExprKind::Sub(Box::new(lhs), Box::new(rhs)).into(),
+    }
+}
+
+// Numbers are moved to the front of a product, so `3 * (2 * x)` becomes `6 * x`.
+fn mul(lhs: Expr, rhs: Expr) -> Expr {
+    if is(&lhs, 0.0) || is(&rhs, 0.0) {
+        return num(0.0);
+    }
+
+    match (&lhs.kind, &rhs.kind) {
+        (ExprKind::Num(a, _), ExprKind::Num(b, _)) => num(a * b),
+        (ExprKind::Num(o, _), _) if *o == 1.0 => rhs,
+        (_, ExprKind::Num(_, _)) => mul(rhs, lhs),
+        (ExprKind::Num(a, _), ExprKind::Mult(b, x)) => match value(b) {
+            Some(b) => mul(num(a * b), (**x).clone()),
+            None => ExprKind::Mult(Box::new(lhs), Box::new(rhs)).into(),
+        },
+        _ => ExprKind::Mult(Box::new(lhs), Box::new(rhs)).into(),
+    }
+}
+
+fn div(lhs: Expr, rhs: Expr) -> Expr {
+    match (value(&lhs), value(&rhs)) {
+        (Some(a), Some(b)) if b != 0.0 => num(a / b),
+        (Some(0.0), _) | (_, Some(1.0)) => lhs,
+        _ => ExprKind::Div(Box::new(lhs), Box::new(rhs)).into(),
+    }
+}
+
+fn neg(expr: Expr) -> Expr {
+    match expr.kind {
+        ExprKind::Num(x, _) => num(-x),
+        ExprKind::Negative(x) => *x,
+        _ => ExprKind::Negative(Box::new(expr)).into(),
+    }
+}
+
+fn pow(lhs: Expr, rhs: Expr) -> Expr {
+    if is(&rhs, 1.0) {
+        return lhs;
+    }
+
+    ExprKind::Pow(Box::new(lhs), Box::new(rhs)).into()
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    ExprKind::Call(name.to_string(), vec![arg]).into()
+}
+
+fn choose(cond: ExprKind, then: Expr, otherwise: Expr) -> Expr {
+    if then == otherwise {
+        return then;
+    }
+
+    ExprKind::If(Box::new(cond.into()), Box::new(then), Box::new(otherwise)).into()
+}
+
+// The derivative of `expr` with respect to `var`, where every other name is a
+// constant. Calls can only be to built-ins, user functions have to be inlined first.
+// `floor`, `ceil`, `round` and `%` have jumps, the derivative ignores them like it
+// does the kinks of `abs`, `min` and `max`.
+pub fn diff(expr: &Expr, var: &str) -> Result<Expr, DiffError> {
+    use ExprKind::*;
+
+    let d = |expr: &Expr| diff(expr, var);
+
+    Ok(match &expr.kind {
+        Num(_, _) | Quantity(_, _) => num(0.0),
+        Var(name) => num(if name == var { 1.0 } else { 0.0 }),
+        Negative(x) => neg(d(x)?),
+        Invert(x) => neg(div(d(x)?, pow((**x).clone(), num(2.0)))),
+
+        Add(lhs, rhs) => add(d(lhs)?, d(rhs)?),
+        Sub(lhs, rhs) => sub(d(lhs)?, d(rhs)?),
+        Mult(lhs, rhs) => add(mul(d(lhs)?, (**rhs).clone()), mul((**lhs).clone(), d(rhs)?)),
+        Div(lhs, rhs) => match d(rhs)? {
+            drhs if is(&drhs, 0.0) => div(d(lhs)?, (**rhs).clone()),
+            drhs => div(
+                sub(mul(d(lhs)?, (**rhs).clone()), mul((**lhs).clone(), drhs)),
+                pow((**rhs).clone(), num(2.0)),
+            ),
+        },
+        // `a % b` is `a - b * trunc(a / b)`.
+        Mod(lhs, rhs) => {
+            let quotient = || div((**lhs).clone(), (**rhs).clone());
+            let trunc = choose(
+                Less(Box::new(quotient()), Box::new(num(0.0))),
+                call("ceil", quotient()),
+                call("floor", quotient()),
+            );
+
+            sub(d(lhs)?, mul(d(rhs)?, trunc))
+        }
+        Pow(base, exponent) => {
+            let (dbase, dexponent) = (d(base)?, d(exponent)?);
+            let (base, exponent) = ((**base).clone(), (**exponent).clone());
+
+            if is(&dexponent, 0.0) {
+                let lowered = pow(base, sub(exponent.clone(), num(1.0)));
+                mul(dbase, mul(exponent, lowered))
+            } else if is(&dbase, 0.0) {
+                mul(
+                    dexponent,
+                    mul(pow(base.clone(), exponent), call("ln", base)),
+                )
+            } else {
+                mul(
+                    pow(base.clone(), exponent.clone()),
+                    add(
+                        mul(dexponent, call("ln", base.clone())),
+                        div(mul(exponent, dbase), base),
+                    ),
+                )
+            }
+        }
+        If(cond, then, otherwise) => choose(cond.kind.clone(), d(then)?, d(otherwise)?),
+
+        Call(name, args) => match (name.as_str(), args.as_slice()) {
+            ("sqrt", [x]) => div(d(x)?, mul(num(2.0), call("sqrt", x.clone()))),
+            ("abs", [x]) => mul(d(x)?, div(x.clone(), call("abs", x.clone()))),
+            ("floor" | "ceil" | "round", [_]) => num(0.0),
+            ("exp", [x]) => mul(d(x)?, call("exp", x.clone())),
+            ("ln", [x]) => div(d(x)?, x.clone()),
+            ("log10", [x]) => div(d(x)?, mul(x.clone(), call("ln", num(10.0)))),
+            ("sin", [x]) => mul(d(x)?, call("cos", x.clone())),
+            ("cos", [x]) => neg(mul(d(x)?, call("sin", x.clone()))),
+            ("tan", [x]) => div(d(x)?, pow(call("cos", x.clone()), num(2.0))),
+            ("min", [a, b]) => choose(
+                LessEq(Box::new(a.clone()), Box::new(b.clone())),
+                d(a)?,
+                d(b)?,
+            ),
+            ("max", [a, b]) => choose(
+                GreaterEq(Box::new(a.clone()), Box::new(b.clone())),
+                d(a)?,
+                d(b)?,
+            ),
+            _ => return Err(error(expr, format!("cannot differentiate `{}`", name))),
+        },
+
+        Bool(_)
+        | Not(_)
+        | Eq(_, _)
+        | NotEq(_, _)
+        | Less(_, _)
+        | LessEq(_, _)
+        | Greater(_, _)
+        | GreaterEq(_, _)
+        | And(_, _)
+        | Or(_, _) => {
+            return Err(error(
+                expr,
+                "cannot differentiate a boolean expression".to_string(),
+            ))
+        }
+        Lambda(_, _) => return Err(error(expr, "cannot differentiate a lambda".to_string())),
+        List(_) => return Err(error(expr, "cannot differentiate a list".to_string())),
+    })
+}
+
+#[cfg(test)]
+fn derive_str(src: &str, var: &str) -> Result<String, String> {
+    derive(&crate::parser::parse(src).unwrap(), var)
+        .map(|expr| crate::format::format_expr(&expr))
+        .map_err(|err| err.message)
+}
+
+#[test]
+fn test_derive_polynomials() {
+    assert_eq!(
+        derive_str("3 * x ^ 2 + 2 * x + 1", "x"),
+        Ok("6 * x + 2".to_string())
+    );
+    assert_eq!(
+        derive_str("x * y - y ^ 3", "y"),
+        Ok("x - 3 * y ^ 2".to_string())
+    );
+    assert_eq!(derive_str("rate * n", "x"), Ok("0".to_string()));
+    assert_eq!(derive_str("-x / 4", "x"), Ok("-0.25".to_string()));
+}
+
+#[test]
+fn test_derive_builtins_use_the_chain_rule() {
+    assert_eq!(
+        derive_str("sin(2 * x)", "x"),
+        Ok("2 * cos(2 * x)".to_string())
+    );
+    assert_eq!(derive_str("ln(x)", "x"), Ok("1 / x".to_string()));
+    assert_eq!(
+        derive_str("exp(x ^ 2)", "x"),
+        Ok("2 * x * exp(x ^ 2)".to_string())
+    );
+    assert_eq!(derive_str("floor(x) + pi", "x"), Ok("0".to_string()));
+}
+
+#[test]
+fn test_derive_inlines_definitions() {
+    assert_eq!(
+        derive_str("let k = 3; fn sq(x) = x * x; let y = k * x; sq(y)", "x"),
+        Ok("9 * x + 9 * x".to_string())
+    );
+    // A function sees the `k` it was defined with, and the `let` of `x` stays a name.
+    assert_eq!(
+        derive_str(
+            "let k = 2; fn f(y) = k * y; let k = 10; let x = 5; f(x) + k",
+            "x"
+        ),
+        Ok("2".to_string())
+    );
+}
+
+#[test]
+fn test_derive_errors() {
+    assert_eq!(
+        derive_str("fn f(x) = if x < 1 then x else f(x - 1); f(x)", "x"),
+        Err("cannot differentiate recursive function `f`".to_string())
+    );
+    assert_eq!(
+        derive_str("x > 1", "x"),
+        Err("cannot differentiate a boolean expression".to_string())
+    );
+    assert_eq!(
+        derive_str("g(x)", "x"),
+        Err("cannot differentiate `g`".to_string())
+    );
+}
+
+#[test]
+fn test_derive_matches_finite_differences() {
+    use crate::eval::{eval, eval_program, Env, Value};
+
+    let env = |x| {
+        Env::new()
+            .bind("x", Value::Num(x))
+            .bind("a", Value::Num(1.5))
+    };
+
+    for src in [
+        "x ^ 3 - 4 * x + a",
+        "(x + 1) / (x * x + 1)",
+        "~(x + 2) + ~a",
+        "x ^ x + 2 ^ x + x ^ a",
+        "sqrt(x) * abs(x - 3) + log10(x)",
+        "tan(x) - cos(x ^ 2) / exp(-x)",
+        "min(x, 2) * max(x * x, 3)",
+        "if x < 1 then x ^ 2 else -x",
+        "x % 0.75 + 7 % x",
+        "fn f(y, z) = y * z + ln(y); let b = f(x, a); f(b, b)",
+    ] {
+        let program = crate::parser::parse(src).unwrap();
+        let derivative = derive(&program, "x").unwrap();
+        let f = |x| {
+            eval_program(&program, &env(x), &mut |_| {})
+                .unwrap()
+                .unwrap()
+                .num()
+                .unwrap()
+        };
+
+        for x in [0.8, 1.3, 2.6] {
+            let h = 1e-6;
+            let expected = (f(x + h) - f(x - h)) / (2.0 * h);
+            let actual = eval(&derivative, &env(x)).unwrap().num().unwrap();
+
+            assert!(
+                (expected - actual).abs() <= 1e-4 * expected.abs().max(1.0),
+                "d/dx {} at {}: expected {} but {} = {}",
+                src,
+                x,
+                expected,
+                crate::format::format_expr(&derivative),
+                actual
+            );
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::ast::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+// Every error found in a source, in the order they were found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn render(&self, filename: &str, src: &str) -> String {
+        self.0
+            .iter()
+            .map(|diag| render(filename, src, diag))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(iter: I) -> Self {
+        Diagnostics(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages = self.0.iter().map(|diag| diag.message.as_str());
+        write!(f, "{}", messages.collect::<Vec<_>>().join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+pub fn render(filename: &str, src: &str, diag: &Diagnostic) -> String {
+    let span = &diag.span;
+    let (line, col) = line_col(src, span.start);
+    let text = src.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    let width = src[span.start..span.end.min(line_end(src, span.start))]
+        .chars()
+        .count()
+        .max(1);
+
+    format!(
+        "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+        diag.message,
+        gutter,
+        filename,
+        line,
+        col,
+        gutter,
+        line,
+        text,
+        gutter,
+        " ".repeat(col - 1),
+        "^".repeat(width)
+    )
+}
+
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn line_end(src: &str, offset: usize) -> usize {
+    src[offset..].find('\n').map_or(src.len(), |i| offset + i)
+}
+
+#[cfg(test)]
+fn render_str(src: &str) -> Vec<String> {
+    crate::parser::parse(src)
+        .unwrap_err()
+        .iter()
+        .map(|err| render("test.x", src, &err.into()))
+        .collect()
+}
+
+#[test]
+fn test_line_col() {
+    let src = "1 +\n  2 *\nπ3";
+
+    assert_eq!(line_col(src, 0), (1, 1));
+    assert_eq!(line_col(src, 3), (1, 4));
+    assert_eq!(line_col(src, 6), (2, 3));
+    assert_eq!(line_col(src, 12), (3, 2));
+}
+
+#[test]
+fn test_render_unexpected_token() {
+    assert_eq!(
+        render_str("let x = 1;\nx + * 2"),
+        vec![concat!(
+            "error: unexpected `*`, expected `!`, `(`, `-`, `~`, boolean, `if`, lambda, list, name or number\n",
+            " --> test.x:2:5\n",
+            "  |\n",
+            "2 | x + * 2\n",
+            "  |     ^",
+        )]
+    );
+}
+
+#[test]
+fn test_render_custom_error_underlines_whole_span() {
+    assert_eq!(
+        render_str("1 + 0x1_2z"),
+        vec![concat!(
+            "error: invalid digit `z` in hexadecimal literal `0x1_2z`\n",
+            " --> test.x:1:5\n",
+            "  |\n",
+            "1 | 1 + 0x1_2z\n",
+            "  |     ^^^^^^",
+        )]
+    );
+}
+
+#[test]
+fn test_render_unexpected_end_of_input() {
+    assert_eq!(
+        render_str("1 +"),
+        vec![concat!(
+            "error: unexpected end of input, expected `!`, `(`, `-`, `~`, boolean, `if`, lambda, list, name or number\n",
+            " --> test.x:1:4\n",
+            "  |\n",
+            "1 | 1 +\n",
+            "  |    ^",
+        )]
+    );
+}
+
+#[test]
+fn test_render_several_errors() {
+    let errs = render_str("let a = 1 +;\nlet b = * 2;\na");
+
+    assert_eq!(errs.len(), 2);
+    assert!(errs[0].contains("test.x:1:12"));
+    assert!(errs[1].contains("test.x:2:9"));
+}
@@ -0,0 +1,157 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::format::{format_expr, format_number};
+
+// Writes the tree as a Graphviz digraph, one box per node, for `dot -Tsvg`.
+struct Dot {
+    out: String,
+    nodes: usize,
+}
+
+impl Dot {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+
+        self.nodes += 1;
+        self.out += &format!("    n{} [label=\"{}\"];\n", id, label);
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: Option<&str>) {
+        match label {
+            Some(label) => {
+                self.out += &format!("    n{} -> n{} [label=\"{}\"];\n", from, to, label)
+            }
+            None => self.out += &format!("    n{} -> n{};\n", from, to),
+        }
+    }
+
+    fn children(&mut self, label: &str, children: &[&Expr]) -> usize {
+        let id = self.node(label);
+
+        for child in children {
+            let child = self.expr(child);
+            self.edge(id, child, None);
+        }
+
+        id
+    }
+
+    fn expr(&mut self, expr: &Expr) -> usize {
+        match &expr.kind {
+            ExprKind::Num(x, _) => self.node(&format_number(*x)),
+            ExprKind::Quantity(_, _) => self.node(&format_expr(expr)),
+            ExprKind::Bool(b) => self.node(&b.to_string()),
+            ExprKind::Var(name) => self.node(name),
+            ExprKind::Call(name, args) => {
+                let args = args.iter().collect::<Vec<_>>();
+                self.children(&format!("{}()", name), &args)
+            }
+            ExprKind::Negative(x) => self.children("-", &[x]),
+            ExprKind::Invert(x) => self.children("~", &[x]),
+            ExprKind::Not(x) => self.children("!", &[x]),
+
+            ExprKind::Add(lhs, rhs) => self.children("+", &[lhs, rhs]),
+            ExprKind::Sub(lhs, rhs) => self.children("-", &[lhs, rhs]),
+            ExprKind::Mult(lhs, rhs) => self.children("*", &[lhs, rhs]),
+            ExprKind::Div(lhs, rhs) => self.children("/", &[lhs, rhs]),
+            ExprKind::Mod(lhs, rhs) => self.children("%", &[lhs, rhs]),
+            ExprKind::Pow(lhs, rhs) => self.children("^", &[lhs, rhs]),
+            ExprKind::Eq(lhs, rhs) => self.children("==", &[lhs, rhs]),
+            ExprKind::NotEq(lhs, rhs) => self.children("!=", &[lhs, rhs]),
+            ExprKind::Less(lhs, rhs) => self.children("<", &[lhs, rhs]),
+            ExprKind::LessEq(lhs, rhs) => self.children("<=", &[lhs, rhs]),
+            ExprKind::Greater(lhs, rhs) => self.children(">", &[lhs, rhs]),
+            ExprKind::GreaterEq(lhs, rhs) => self.children(">=", &[lhs, rhs]),
+            ExprKind::And(lhs, rhs) => self.children("&&", &[lhs, rhs]),
+            ExprKind::Or(lhs, rhs) => self.children("||", &[lhs, rhs]),
+
+            // The branches are labelled, since the layout doesn't always keep them in order.
+            ExprKind::If(cond, then, otherwise) => {
+                let id = self.node("if");
+
+                for (label, child) in [("cond", cond), ("then", then), ("else", otherwise)] {
+                    let child = self.expr(child);
+                    self.edge(id, child, Some(label));
+                }
+
+                id
+            }
+            ExprKind::Lambda(params, body) => {
+                self.children(&format!("|{}|", params.join(", ")), &[body])
+            }
+            ExprKind::List(items) => {
+                let items = items.iter().collect::<Vec<_>>();
+                self.children("[]", &items)
+            }
+        }
+    }
+}
+
+pub fn to_dot(program: &Program) -> String {
+    let mut dot = Dot {
+        out: String::from("digraph ast {\n    node [shape=box, fontname=monospace];\n"),
+        nodes: 0,
+    };
+
+    let root = dot.node("program");
+
+    for stmt in &program.stmts {
+        let (id, body) = match stmt {
+            Stmt::Let(name, value) => (dot.node(&format!("let {}", name)), value),
+            Stmt::Fn(name, params, body) => (
+                dot.node(&format!("fn {}({})", name, params.join(", "))),
+                body,
+            ),
+            Stmt::Print(value) => (dot.node("print"), value),
+        };
+
+        let body = dot.expr(body);
+
+        dot.edge(root, id, None);
+        dot.edge(id, body, None);
+    }
+
+    if let Some(result) = &program.result {
+        let result = dot.expr(result);
+        dot.edge(root, result, Some("result"));
+    }
+
+    dot.out + "}\n"
+}
+
+#[test]
+fn test_dot() {
+    let program = crate::parser::parse("let x = 2; if x > 1 then -x else f(x, 0.5)").unwrap();
+
+    assert_eq!(
+        to_dot(&program),
+        concat!(
+            "digraph ast {\n",
+            "    node [shape=box, fontname=monospace];\n",
+            "    n0 [label=\"program\"];\n",
+            "    n1 [label=\"let x\"];\n",
+            "    n2 [label=\"2\"];\n",
+            "    n0 -> n1;\n",
+            "    n1 -> n2;\n",
+            "    n3 [label=\"if\"];\n",
+            "    n4 [label=\">\"];\n",
+            "    n5 [label=\"x\"];\n",
+            "    n4 -> n5;\n",
+            "    n6 [label=\"1\"];\n",
+            "    n4 -> n6;\n",
+            "    n3 -> n4 [label=\"cond\"];\n",
+            "    n7 [label=\"-\"];\n",
+            "    n8 [label=\"x\"];\n",
+            "    n7 -> n8;\n",
+            "    n3 -> n7 [label=\"then\"];\n",
+            "    n9 [label=\"f()\"];\n",
+            "    n10 [label=\"x\"];\n",
+            "    n9 -> n10;\n",
+            "    n11 [label=\"0.5\"];\n",
+            "    n9 -> n11;\n",
+            "    n3 -> n9 [label=\"else\"];\n",
+            "    n0 -> n3 [label=\"result\"];\n",
+            "}\n",
+        )
+    );
+}
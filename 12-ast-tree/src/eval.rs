@@ -0,0 +1,1181 @@
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt};
+use crate::builtins;
+use crate::diagnostics::Diagnostic;
+use crate::format::format_expr;
+use crate::units::Quantity;
+use std::fmt;
+use std::rc::Rc;
+
+pub const MAX_CALL_DEPTH: usize = 200;
+
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    DivisionByZero,
+    TypeMismatch {
+        expected: &'static str,
+        found: Value,
+    },
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    NotAValue(String),
+    NotAFunction(String),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursionLimit(String),
+    Overflow,
+    Inexact(String),
+    Units(String),
+    Host(String),
+    Unsupported(String),
+    CompareFunctions,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::TypeMismatch { expected, found } => write!(
+                f,
+                "expected a {} but found {} `{}`",
+                expected,
+                found.type_name(),
+                found
+            ),
+            EvalError::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            EvalError::UndefinedFunction(name) => {
+                write!(f, "undefined function `{}`", name)?;
+
+                match builtins::suggest(name) {
+                    Some(builtin) => write!(f, ", did you mean `{}`?", builtin),
+                    None => Ok(()),
+                }
+            }
+            EvalError::NotAValue(name) => {
+                write!(f, "`{}` is a function and must be called", name)
+            }
+            EvalError::NotAFunction(name) => write!(f, "`{}` is not a function", name),
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s) but {} were given",
+                name, expected, found
+            ),
+            EvalError::RecursionLimit(name) => write!(
+                f,
+                "calls to `{}` nested deeper than {} levels",
+                name, MAX_CALL_DEPTH
+            ),
+            EvalError::Overflow => {
+                write!(f, "number does not fit in {} bits", crate::exact::MAX_BITS)
+            }
+            EvalError::Inexact(expr) => write!(f, "`{}` has no exact value", expr),
+            EvalError::Units(message)
+            | EvalError::Host(message)
+            | EvalError::Unsupported(message) => {
+                write!(f, "{}", message)
+            }
+            EvalError::CompareFunctions => write!(f, "functions cannot be compared"),
+        }
+    }
+}
+
+impl EvalError {
+    pub fn at(self, span: &Span) -> RuntimeError {
+        RuntimeError {
+            error: self,
+            span: span.clone(),
+        }
+    }
+}
+
+// An error from tree walking, with the span of the node that caused it: the operand
+// with the wrong type, the division by zero or the call that failed.
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub error: EvalError,
+    pub span: Span,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(err: &RuntimeError) -> Self {
+        Diagnostic {
+            message: err.error.to_string(),
+            span: err.span.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    Quantity(Quantity),
+    Bool(bool),
+    List(Vec<Value>),
+    Closure(Rc<Function>),
+}
+
+// Arithmetic on quantities drops units that cancel out, and the result is a plain
+// number when none are left.
+impl From<Quantity> for Value {
+    fn from(quantity: Quantity) -> Self {
+        match quantity.unit.is_empty() {
+            true => Value::Num(quantity.value),
+            false => Value::Quantity(quantity),
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Num(_) | Value::Quantity(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::List(_) => "list",
+            Value::Closure(_) => "function",
+        }
+    }
+
+    pub fn num(self) -> Result<f64, EvalError> {
+        match self {
+            Value::Num(x) => Ok(x),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: self,
+            }),
+        }
+    }
+
+    pub fn quantity(self) -> Result<Quantity, EvalError> {
+        match self {
+            Value::Num(x) => Ok(Quantity::plain(x)),
+            Value::Quantity(quantity) => Ok(quantity),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: self,
+            }),
+        }
+    }
+
+    pub fn bool(self) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "boolean",
+                found: self,
+            }),
+        }
+    }
+
+    pub fn list(self) -> Result<Vec<Value>, EvalError> {
+        match self {
+            Value::List(items) => Ok(items),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "list",
+                found: self,
+            }),
+        }
+    }
+
+    pub fn function(self) -> Result<Rc<Function>, EvalError> {
+        match self {
+            Value::Closure(function) => Ok(function),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "function",
+                found: self,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Num(x) => write!(f, "{}", x),
+            Value::Quantity(quantity) => write!(f, "{}", quantity),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                let items = items.iter().map(Value::to_string).collect::<Vec<_>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Closure(function) => {
+                let body = Box::new(function.body.clone());
+                let lambda = ExprKind::Lambda(function.params.clone(), body);
+                write!(f, "{}", format_expr(&lambda.into()))
+            }
+        }
+    }
+}
+
+pub struct Function<V = Value> {
+    pub params: Vec<String>,
+    pub body: Expr,
+    // The scope the function was defined in, so bodies only see earlier definitions.
+    pub env: Env<V>,
+}
+
+// Lambdas are functions too. Two of them are only equal when they are the same one.
+impl<V> PartialEq for Function<V> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl<V> fmt::Debug for Function<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+// A function provided by the program embedding the language. It gets the evaluated
+// arguments, `arity` of them, and can fail with `EvalError::Host`.
+pub type HostFn<V> = dyn Fn(&[V]) -> Result<V, EvalError>;
+
+pub struct HostFunction<V = Value> {
+    pub arity: usize,
+    pub apply: Box<HostFn<V>>,
+}
+
+pub enum Binding<V = Value> {
+    Value(V),
+    Function(Rc<Function<V>>),
+    Host(Rc<HostFunction<V>>),
+}
+
+struct Scope<V> {
+    name: String,
+    binding: Binding<V>,
+    parent: Option<Rc<Scope<V>>>,
+}
+
+// Environments are immutable: `bind` returns a new scope that shadows its parent, so
+// outer scopes never see bindings made by inner ones. They are generic over the value
+// type so exact evaluation can use them too.
+pub struct Env<V = Value> {
+    head: Option<Rc<Scope<V>>>,
+}
+
+impl<V> Clone for Env<V> {
+    fn clone(&self) -> Self {
+        Env {
+            head: self.head.clone(),
+        }
+    }
+}
+
+impl<V> Default for Env<V> {
+    fn default() -> Self {
+        Env { head: None }
+    }
+}
+
+impl<V: Clone> Env<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&self, name: &str, value: V) -> Env<V> {
+        self.bind_binding(name, Binding::Value(value))
+    }
+
+    pub fn bind_fn(&self, name: &str, function: Rc<Function<V>>) -> Env<V> {
+        self.bind_binding(name, Binding::Function(function))
+    }
+
+    pub fn bind_host(
+        &self,
+        name: &str,
+        arity: usize,
+        apply: impl Fn(&[V]) -> Result<V, EvalError> + 'static,
+    ) -> Env<V> {
+        let apply = Box::new(apply);
+        self.bind_binding(name, Binding::Host(Rc::new(HostFunction { arity, apply })))
+    }
+
+    fn bind_binding(&self, name: &str, binding: Binding<V>) -> Env<V> {
+        Env {
+            head: Some(Rc::new(Scope {
+                name: name.to_string(),
+                binding,
+                parent: self.head.clone(),
+            })),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Binding<V>> {
+        let mut scope = self.head.as_ref();
+
+        while let Some(s) = scope {
+            if s.name == name {
+                return Some(&s.binding);
+            }
+
+            scope = s.parent.as_ref();
+        }
+
+        None
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<V> {
+        match self.get(name) {
+            Some(Binding::Value(x)) => Some(x.clone()),
+            _ => None,
+        }
+    }
+}
+
+// `print` statements hand their values to `out`, so callers decide where they go.
+pub fn eval_program(
+    program: &Program,
+    env: &Env,
+    out: &mut dyn FnMut(Value),
+) -> Result<Option<Value>, RuntimeError> {
+    let mut env = env.clone();
+
+    for stmt in &program.stmts {
+        env = exec(stmt, &env, out)?;
+    }
+
+    program
+        .result
+        .as_ref()
+        .map(|result| eval(result, &env))
+        .transpose()
+}
+
+pub fn exec(stmt: &Stmt, env: &Env, out: &mut dyn FnMut(Value)) -> Result<Env, RuntimeError> {
+    match stmt {
+        Stmt::Let(name, value) => Ok(env.bind(name, eval(value, env)?)),
+        Stmt::Fn(name, params, body) => Ok(env.bind_fn(
+            name,
+            Rc::new(Function {
+                params: params.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            }),
+        )),
+        Stmt::Print(value) => {
+            out(eval(value, env)?);
+            Ok(env.clone())
+        }
+    }
+}
+
+pub fn eval(expr: &Expr, env: &Env) -> Result<Value, RuntimeError> {
+    eval_at(expr, env, 0)
+}
+
+// `~x` is the multiplicative inverse of `x`, so `~0` is a division by zero. Both
+// operands of a binary operator are evaluated before their types are checked, except
+// for `&&` and `||` which skip the right operand when the left one decides the result.
+fn eval_at(expr: &Expr, env: &Env, depth: usize) -> Result<Value, RuntimeError> {
+    let at = |err: EvalError| err.at(&expr.span);
+    let eval = |expr: &Expr| eval_at(expr, env, depth);
+    let quantity = |value: Value, expr: &Expr| value.quantity().map_err(|err| err.at(&expr.span));
+    let boolean = |expr: &Expr| eval(expr)?.bool().map_err(|err| err.at(&expr.span));
+    let number = |expr: &Expr| quantity(eval(expr)?, expr);
+    let numbers = |lhs: &Expr, rhs: &Expr| {
+        let (a, b) = (eval(lhs)?, eval(rhs)?);
+        Ok::<_, RuntimeError>((quantity(a, lhs)?, quantity(b, rhs)?))
+    };
+    let compare = |lhs, rhs, op: fn(&f64, &f64) -> bool| {
+        let (lhs, rhs) = numbers(lhs, rhs)?;
+        let (a, b) = lhs.align(&rhs, "compare").map_err(at)?;
+        Ok(Value::Bool(op(&a, &b)))
+    };
+    let equal = |lhs: &Expr, rhs: &Expr| {
+        let (a, b) = (eval(lhs)?, eval(rhs)?);
+        equal(a, b).map_err(|err| err.at(&rhs.span))
+    };
+
+    match &expr.kind {
+        ExprKind::Num(x, _) => Ok(Value::Num(*x)),
+        ExprKind::Quantity(x, unit) => Ok(Quantity::new(*x, unit.clone()).map_err(at)?.into()),
+        ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+        ExprKind::Var(name) => match env.get(name) {
+            Some(Binding::Value(x)) => Ok(x.clone()),
+            Some(Binding::Function(_) | Binding::Host(_)) => {
+                Err(at(EvalError::NotAValue(name.clone())))
+            }
+            None => builtins::constant(name)
+                .map(Value::Num)
+                .ok_or_else(|| at(EvalError::UndefinedVariable(name.clone()))),
+        },
+        ExprKind::Call(name, args) => eval_call(expr, name, args, env, depth),
+        ExprKind::Negative(x) => {
+            arithmetic(&expr.kind, Quantity::plain(0.0), number(x)?).map_err(at)
+        }
+        ExprKind::Invert(x) => arithmetic(&expr.kind, Quantity::plain(1.0), number(x)?).map_err(at),
+        ExprKind::Not(x) => Ok(Value::Bool(!boolean(x)?)),
+
+        ExprKind::Add(lhs, rhs)
+        | ExprKind::Sub(lhs, rhs)
+        | ExprKind::Mult(lhs, rhs)
+        | ExprKind::Div(lhs, rhs)
+        | ExprKind::Mod(lhs, rhs)
+        | ExprKind::Pow(lhs, rhs) => {
+            let (lhs, rhs) = numbers(lhs, rhs)?;
+            arithmetic(&expr.kind, lhs, rhs).map_err(at)
+        }
+
+        ExprKind::Eq(lhs, rhs) => Ok(Value::Bool(equal(lhs, rhs)?)),
+        ExprKind::NotEq(lhs, rhs) => Ok(Value::Bool(!equal(lhs, rhs)?)),
+        ExprKind::Less(lhs, rhs) => compare(lhs, rhs, f64::lt),
+        ExprKind::LessEq(lhs, rhs) => compare(lhs, rhs, f64::le),
+        ExprKind::Greater(lhs, rhs) => compare(lhs, rhs, f64::gt),
+        ExprKind::GreaterEq(lhs, rhs) => compare(lhs, rhs, f64::ge),
+        ExprKind::And(lhs, rhs) => Ok(Value::Bool(boolean(lhs)? && boolean(rhs)?)),
+        ExprKind::Or(lhs, rhs) => Ok(Value::Bool(boolean(lhs)? || boolean(rhs)?)),
+
+        ExprKind::If(cond, then, otherwise) => {
+            if boolean(cond)? {
+                eval(then)
+            } else {
+                eval(otherwise)
+            }
+        }
+        ExprKind::Lambda(params, body) => Ok(Value::Closure(Rc::new(Function {
+            params: params.clone(),
+            body: (**body).clone(),
+            env: env.clone(),
+        }))),
+        ExprKind::List(items) => Ok(Value::List(
+            items.iter().map(eval).collect::<Result<_, _>>()?,
+        )),
+    }
+}
+
+fn eval_call(
+    expr: &Expr,
+    name: &str,
+    args: &[Expr],
+    env: &Env,
+    depth: usize,
+) -> Result<Value, RuntimeError> {
+    let at = |err: EvalError| err.at(&expr.span);
+    let eval = |expr: &Expr| eval_at(expr, env, depth);
+    let num = |value: Value, expr: &Expr| value.num().map_err(|err| err.at(&expr.span));
+
+    // Binding a function to its own name inside the body is what allows
+    // recursion. Lambdas only see the scope they were created in.
+    let (function, scope) = match env.get(name) {
+        Some(Binding::Function(function)) => (
+            function.clone(),
+            function.env.bind_fn(name, function.clone()),
+        ),
+        Some(Binding::Value(Value::Closure(function))) => (function.clone(), function.env.clone()),
+        Some(Binding::Host(host)) => {
+            let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            return call_host(expr, name, host, values);
+        }
+        Some(Binding::Value(_)) => return Err(at(EvalError::NotAFunction(name.to_string()))),
+        None if builtins::higher_order(name).is_some() => {
+            let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            return higher_order(expr, name, values, args, depth);
+        }
+        None => {
+            let (_, builtin) = builtins::builtin(name)
+                .ok_or_else(|| at(EvalError::UndefinedFunction(name.to_string())))?;
+
+            let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            check_arity(name, builtin.arity, values.len()).map_err(at)?;
+
+            if values
+                .iter()
+                .any(|value| matches!(value, Value::Quantity(_)))
+            {
+                return apply_to_quantities(expr, name, values, args);
+            }
+
+            let values = values
+                .into_iter()
+                .zip(args)
+                .map(|(value, arg)| num(value, arg))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return Ok(Value::Num(builtin.apply(&values)));
+        }
+    };
+
+    let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+    call(expr, name, &function, values, scope, depth)
+}
+
+// Operators that need compatible units convert the right operand to the unit of the
+// left one, which is also the unit of the result. Unary operators get their operand
+// on the right. This is kept out of `eval_at` so its frames stay small for recursion.
+fn arithmetic(kind: &ExprKind, lhs: Quantity, rhs: Quantity) -> Result<Value, EvalError> {
+    let aligned = |op: &str, f: fn(f64, f64) -> Result<f64, EvalError>| {
+        let (a, b) = lhs.align(&rhs, op)?;
+        Ok(Value::from(lhs.with_value(f(a, b)?)))
+    };
+
+    match kind {
+        ExprKind::Negative(_) => Ok(rhs.with_value(-rhs.value).into()),
+        ExprKind::Add(_, _) => aligned("add", |a, b| Ok(a + b)),
+        ExprKind::Sub(_, _) => aligned("subtract", |a, b| Ok(a - b)),
+        ExprKind::Mod(_, _) => aligned("take the remainder of", remainder),
+        ExprKind::Mult(_, _) => Ok((lhs * rhs)?.into()),
+        ExprKind::Div(_, _) | ExprKind::Invert(_) => {
+            divide(lhs.value, rhs.value)?;
+            Ok((lhs / rhs)?.into())
+        }
+        _ if !rhs.unit.is_empty() => Err(EvalError::Units(
+            "an exponent can't have a unit".to_string(),
+        )),
+        _ => Ok(lhs.pow(rhs.value)?.into()),
+    }
+}
+
+fn apply_to_quantities(
+    expr: &Expr,
+    name: &str,
+    values: Vec<Value>,
+    args: &[Expr],
+) -> Result<Value, RuntimeError> {
+    let values = values
+        .into_iter()
+        .zip(args)
+        .map(|(value, arg)| value.quantity().map_err(|err| err.at(&arg.span)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Quantity::apply(name, values)
+        .map_err(|err| err.at(&expr.span))?
+        .into())
+}
+
+// `map`, `filter` and `fold` call their function once for every item of the list, in
+// order. Errors in the function point at the call of the built-in.
+fn higher_order(
+    expr: &Expr,
+    name: &str,
+    values: Vec<Value>,
+    args: &[Expr],
+    depth: usize,
+) -> Result<Value, RuntimeError> {
+    let at = |i: usize| move |err: EvalError| err.at(&args[i].span);
+    let arity = builtins::higher_order(name).unwrap_or(0);
+
+    check_arity(name, arity, values.len()).map_err(|err| err.at(&expr.span))?;
+
+    let mut values = values.into_iter();
+    let mut next = || values.next().unwrap();
+    let apply = |i: usize, function: &Rc<Function>, values| {
+        let name = format_expr(&args[i]);
+        call(expr, &name, function, values, function.env.clone(), depth)
+    };
+
+    match name {
+        "map" => {
+            let (items, function) = (
+                next().list().map_err(at(0))?,
+                next().function().map_err(at(1))?,
+            );
+
+            items
+                .into_iter()
+                .map(|item| apply(1, &function, vec![item]))
+                .collect::<Result<_, _>>()
+                .map(Value::List)
+        }
+        "filter" => {
+            let (items, function) = (
+                next().list().map_err(at(0))?,
+                next().function().map_err(at(1))?,
+            );
+            let mut kept = Vec::new();
+
+            for item in items {
+                if apply(1, &function, vec![item.clone()])?
+                    .bool()
+                    .map_err(at(1))?
+                {
+                    kept.push(item);
+                }
+            }
+
+            Ok(Value::List(kept))
+        }
+        "fold" => {
+            let items = next().list().map_err(at(0))?;
+            let mut acc = next();
+            let function = next().function().map_err(at(2))?;
+
+            for item in items {
+                acc = apply(2, &function, vec![acc, item])?;
+            }
+
+            Ok(acc)
+        }
+        "apply" => {
+            let function = next().function().map_err(at(0))?;
+            apply(0, &function, next().list().map_err(at(1))?)
+        }
+        _ => Ok(Value::Num(next().list().map_err(at(0))?.len() as f64)),
+    }
+}
+
+fn call(
+    expr: &Expr,
+    name: &str,
+    function: &Rc<Function>,
+    values: Vec<Value>,
+    scope: Env,
+    depth: usize,
+) -> Result<Value, RuntimeError> {
+    check_arity(name, function.params.len(), values.len()).map_err(|err| err.at(&expr.span))?;
+
+    if depth >= MAX_CALL_DEPTH {
+        return Err(EvalError::RecursionLimit(name.to_string()).at(&expr.span));
+    }
+
+    let mut scope = scope;
+
+    for (param, value) in function.params.iter().zip(values) {
+        scope = scope.bind(param, value);
+    }
+
+    eval_at(&function.body, &scope, depth + 1)
+}
+
+// Errors from the host function point at the whole call.
+pub fn call_host<V>(
+    expr: &Expr,
+    name: &str,
+    host: &HostFunction<V>,
+    values: Vec<V>,
+) -> Result<V, RuntimeError> {
+    let at = |err: EvalError| err.at(&expr.span);
+
+    check_arity(name, host.arity, values.len()).map_err(at)?;
+    (host.apply)(&values).map_err(at)
+}
+
+pub fn check_arity(name: &str, expected: usize, found: usize) -> Result<(), EvalError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(EvalError::ArityMismatch {
+            name: name.to_string(),
+            expected,
+            found,
+        })
+    }
+}
+
+// Only values of the same type can be compared, `1 == true` is an error, and so is
+// comparing quantities with incompatible units.
+pub fn equal(lhs: Value, rhs: Value) -> Result<bool, EvalError> {
+    match (lhs, rhs) {
+        (Value::Num(a), Value::Num(b)) => Ok(a == b),
+        (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+        (Value::List(a), Value::List(b)) => {
+            if a.len() != b.len() {
+                return Ok(false);
+            }
+
+            for (a, b) in a.into_iter().zip(b) {
+                if !equal(a, b)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+        (Value::Closure(_), _) | (_, Value::Closure(_)) => Err(EvalError::CompareFunctions),
+        (
+            lhs @ (Value::Num(_) | Value::Quantity(_)),
+            rhs @ (Value::Num(_) | Value::Quantity(_)),
+        ) => {
+            let (a, b) = lhs.quantity()?.align(&rhs.quantity()?, "compare")?;
+            Ok(a == b)
+        }
+        (lhs, rhs) => Err(EvalError::TypeMismatch {
+            expected: lhs.type_name(),
+            found: rhs,
+        }),
+    }
+}
+
+pub fn divide(lhs: f64, rhs: f64) -> Result<f64, EvalError> {
+    if rhs == 0.0 {
+        Err(EvalError::DivisionByZero)
+    } else {
+        Ok(lhs / rhs)
+    }
+}
+
+// `%` keeps the sign of the left operand, like Rust's `%` on floats.
+pub fn remainder(lhs: f64, rhs: f64) -> Result<f64, EvalError> {
+    if rhs == 0.0 {
+        Err(EvalError::DivisionByZero)
+    } else {
+        Ok(lhs % rhs)
+    }
+}
+
+#[cfg(test)]
+fn eval_str(src: &str) -> Result<Value, EvalError> {
+    eval_spanned(src).map_err(|err| err.error)
+}
+
+#[cfg(test)]
+fn eval_spanned(src: &str) -> Result<Value, RuntimeError> {
+    eval_program(
+        &crate::parser::parse(src).unwrap(),
+        &Env::new(),
+        &mut |_| {},
+    )
+    .map(Option::unwrap)
+}
+
+#[test]
+fn test_eval_number() {
+    assert_eq!(eval_str("42"), Ok(Value::Num(42.0)));
+}
+
+#[test]
+fn test_eval_binary_operators() {
+    assert_eq!(eval_str("1 + 2"), Ok(Value::Num(3.0)));
+    assert_eq!(eval_str("1 - 2"), Ok(Value::Num(-1.0)));
+    assert_eq!(eval_str("3 * 4"), Ok(Value::Num(12.0)));
+    assert_eq!(eval_str("3 / 4"), Ok(Value::Num(0.75)));
+}
+
+#[test]
+fn test_eval_product_binds_tighter_than_sum() {
+    assert_eq!(eval_str("1 + 2 * 3 - 4 / 2"), Ok(Value::Num(5.0)));
+}
+
+#[test]
+fn test_eval_negative() {
+    assert_eq!(eval_str("--3"), Ok(Value::Num(3.0)));
+    assert_eq!(eval_str("2 - -3"), Ok(Value::Num(5.0)));
+}
+
+#[test]
+fn test_eval_invert_is_reciprocal() {
+    assert_eq!(eval_str("~4"), Ok(Value::Num(0.25)));
+    assert_eq!(eval_str("~~4"), Ok(Value::Num(4.0)));
+}
+
+#[test]
+fn test_eval_division_by_zero() {
+    assert_eq!(eval_str("1 / 0"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval_str("~0"), Err(EvalError::DivisionByZero));
+}
+
+#[test]
+fn test_eval_parentheses() {
+    assert_eq!(eval_str("(1 + 2) * 3"), Ok(Value::Num(9.0)));
+    assert_eq!(eval_str("2 * (3 - (4 - 5))"), Ok(Value::Num(8.0)));
+    assert_eq!(eval_str("((7))"), Ok(Value::Num(7.0)));
+}
+
+#[test]
+fn test_eval_unary_on_any_operand() {
+    assert_eq!(eval_str("-(1 + 2)"), Ok(Value::Num(-3.0)));
+    assert_eq!(eval_str("~(3 * 4)"), Ok(Value::Num(1.0 / 12.0)));
+    assert_eq!(eval_str("-~4"), Ok(Value::Num(-0.25)));
+    assert_eq!(eval_str("~-(2)"), Ok(Value::Num(-0.5)));
+}
+
+#[test]
+fn test_eval_float_literals() {
+    assert_eq!(eval_str("2.75"), Ok(Value::Num(2.75)));
+    assert_eq!(eval_str(".5"), Ok(Value::Num(0.5)));
+    assert_eq!(eval_str("1e-9"), Ok(Value::Num(1e-9)));
+    assert_eq!(eval_str("2.5E+3"), Ok(Value::Num(2500.0)));
+    assert_eq!(eval_str("1.5 * .5"), Ok(Value::Num(0.75)));
+}
+
+#[test]
+fn test_eval_hex_literals() {
+    assert_eq!(eval_str("0xff"), Ok(Value::Num(255.0)));
+    assert_eq!(eval_str("0XdEaD_bEeF"), Ok(Value::Num(3735928559.0)));
+}
+
+#[test]
+fn test_eval_underscore_separated_literals() {
+    assert_eq!(eval_str("1_000_000"), Ok(Value::Num(1_000_000.0)));
+    assert_eq!(eval_str("1_0.2_5e1_0"), Ok(Value::Num(10.25e10)));
+}
+
+#[test]
+fn test_eval_example() {
+    assert_eq!(
+        eval_str("1 + ~1234 - 2 / 3"),
+        Ok(Value::Num(1.0 + 1.0 / 1234.0 - 2.0 / 3.0))
+    );
+}
+
+#[test]
+fn test_eval_let_bindings() {
+    assert_eq!(
+        eval_str("let rate = 3; let n = 12; rate * n"),
+        Ok(Value::Num(36.0))
+    );
+}
+
+#[test]
+fn test_eval_let_can_shadow_and_reference_previous_binding() {
+    assert_eq!(
+        eval_str("let x = 2; let x = x * x; x + 1"),
+        Ok(Value::Num(5.0))
+    );
+}
+
+#[test]
+fn test_eval_undefined_variable() {
+    assert_eq!(
+        eval_str("let x = 1; x + y"),
+        Err(EvalError::UndefinedVariable("y".to_string()))
+    );
+    assert_eq!(
+        eval_str("let x = x; x"),
+        Err(EvalError::UndefinedVariable("x".to_string()))
+    );
+}
+
+#[test]
+fn test_env_scopes_do_not_leak_to_parent() {
+    let outer = Env::new().bind("x", Value::Num(1.0));
+    let inner = outer.bind("x", Value::Num(2.0)).bind("y", Value::Num(3.0));
+
+    assert_eq!(inner.lookup("x"), Some(Value::Num(2.0)));
+    assert_eq!(inner.lookup("y"), Some(Value::Num(3.0)));
+    assert_eq!(outer.lookup("x"), Some(Value::Num(1.0)));
+    assert_eq!(outer.lookup("y"), None);
+}
+
+#[test]
+fn test_eval_function_call() {
+    assert_eq!(
+        eval_str("fn area(w, h) = w * h; area(3, 4) + area(1, 2)"),
+        Ok(Value::Num(14.0))
+    );
+}
+
+#[test]
+fn test_eval_function_sees_only_earlier_definitions() {
+    assert_eq!(
+        eval_str("let k = 2; fn f(x) = x * k; let k = 10; f(3) + k"),
+        Ok(Value::Num(16.0))
+    );
+    assert_eq!(
+        eval_str("fn f(x) = x * k; let k = 10; f(3)"),
+        Err(EvalError::UndefinedVariable("k".to_string()))
+    );
+}
+
+#[test]
+fn test_eval_parameters_shadow_outer_names() {
+    assert_eq!(
+        eval_str("let x = 100; fn f(x) = x + 1; f(1) + x"),
+        Ok(Value::Num(102.0))
+    );
+}
+
+#[test]
+fn test_eval_recursion_limit() {
+    assert_eq!(
+        eval_str("fn f(x) = f(x + 1); f(0)"),
+        Err(EvalError::RecursionLimit("f".to_string()))
+    );
+}
+
+#[test]
+fn test_eval_call_errors() {
+    assert_eq!(
+        eval_str("fn f(a, b) = a + b; f(1)"),
+        Err(EvalError::ArityMismatch {
+            name: "f".to_string(),
+            expected: 2,
+            found: 1
+        })
+    );
+    assert_eq!(
+        eval_str("g(1)"),
+        Err(EvalError::UndefinedFunction("g".to_string()))
+    );
+    assert_eq!(
+        eval_str("let g = 1; g(1)"),
+        Err(EvalError::NotAFunction("g".to_string()))
+    );
+    assert_eq!(
+        eval_str("fn g() = 1; g + 1"),
+        Err(EvalError::NotAValue("g".to_string()))
+    );
+}
+
+#[test]
+fn test_eval_power_and_modulo() {
+    assert_eq!(eval_str("2 ^ 3 ^ 2"), Ok(Value::Num(512.0)));
+    assert_eq!(eval_str("-2 ^ 2"), Ok(Value::Num(-4.0)));
+    assert_eq!(eval_str("2 ^ -1"), Ok(Value::Num(0.5)));
+    assert_eq!(eval_str("2 * 3 ^ 2"), Ok(Value::Num(18.0)));
+    assert_eq!(eval_str("7 % 3 * 2"), Ok(Value::Num(2.0)));
+    assert_eq!(eval_str("-7 % 3"), Ok(Value::Num(-1.0)));
+    assert_eq!(eval_str("1 % 0"), Err(EvalError::DivisionByZero));
+}
+
+#[test]
+fn test_eval_builtins() {
+    assert_eq!(eval_str("sqrt(16) + abs(-2)"), Ok(Value::Num(6.0)));
+    assert_eq!(eval_str("min(3, 4) * max(3, 4)"), Ok(Value::Num(12.0)));
+    assert_eq!(eval_str("floor(2.7) + ln(e)"), Ok(Value::Num(3.0)));
+    assert_eq!(eval_str("sin(0) + cos(0)"), Ok(Value::Num(1.0)));
+    assert_eq!(eval_str("pi"), Ok(Value::Num(std::f64::consts::PI)));
+}
+
+#[test]
+fn test_eval_builtins_can_be_shadowed() {
+    assert_eq!(eval_str("fn sqrt(x) = x; sqrt(16)"), Ok(Value::Num(16.0)));
+    assert_eq!(eval_str("let e = 2; e"), Ok(Value::Num(2.0)));
+    assert_eq!(eval_str("fn f(pi) = pi; f(3)"), Ok(Value::Num(3.0)));
+}
+
+#[test]
+fn test_eval_builtin_errors() {
+    assert_eq!(
+        eval_str("max(1)"),
+        Err(EvalError::ArityMismatch {
+            name: "max".to_string(),
+            expected: 2,
+            found: 1
+        })
+    );
+    assert_eq!(
+        eval_str("sqr(4)").unwrap_err().to_string(),
+        "undefined function `sqr`, did you mean `sqrt`?"
+    );
+    assert_eq!(
+        eval_str("area(4)").unwrap_err().to_string(),
+        "undefined function `area`"
+    );
+}
+
+#[test]
+fn test_eval_comparisons_and_logic() {
+    assert_eq!(eval_str("1 < 2"), Ok(Value::Bool(true)));
+    assert_eq!(eval_str("2 <= 1 || 3 >= 3"), Ok(Value::Bool(true)));
+    assert_eq!(eval_str("1 + 1 == 2 && !(1 != 1)"), Ok(Value::Bool(true)));
+    assert_eq!(eval_str("true == false"), Ok(Value::Bool(false)));
+    assert_eq!(eval_str("let t = 2 > 1; t"), Ok(Value::Bool(true)));
+}
+
+#[test]
+fn test_eval_short_circuits() {
+    assert_eq!(eval_str("false && 1 / 0 > 0"), Ok(Value::Bool(false)));
+    assert_eq!(eval_str("true || undefined"), Ok(Value::Bool(true)));
+    assert_eq!(
+        eval_str("true && 1 / 0 > 0"),
+        Err(EvalError::DivisionByZero)
+    );
+}
+
+#[test]
+fn test_eval_if() {
+    let tax = "fn tax(income) = if income <= 10000 then 0 \
+               else if income <= 40000 then (income - 10000) * 0.2 \
+               else 6000 + (income - 40000) * 0.4;";
+
+    assert_eq!(eval_str(&format!("{} tax(5000)", tax)), Ok(Value::Num(0.0)));
+    assert_eq!(
+        eval_str(&format!("{} tax(20000)", tax)),
+        Ok(Value::Num(2000.0))
+    );
+    assert_eq!(
+        eval_str(&format!("{} tax(50000)", tax)),
+        Ok(Value::Num(10000.0))
+    );
+    assert_eq!(
+        eval_str("fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); fact(5)"),
+        Ok(Value::Num(120.0))
+    );
+    assert_eq!(eval_str("if true then 1 else 1 / 0"), Ok(Value::Num(1.0)));
+}
+
+#[test]
+fn test_eval_type_errors() {
+    assert_eq!(
+        eval_str("1 + true"),
+        Err(EvalError::TypeMismatch {
+            expected: "number",
+            found: Value::Bool(true)
+        })
+    );
+    assert_eq!(
+        eval_str("if 1 then 2 else 3").unwrap_err().to_string(),
+        "expected a boolean but found number `1`"
+    );
+    assert_eq!(
+        eval_str("1 == false").unwrap_err().to_string(),
+        "expected a number but found boolean `false`"
+    );
+    assert_eq!(
+        eval_str("true && 1").unwrap_err().to_string(),
+        "expected a boolean but found number `1`"
+    );
+    assert_eq!(
+        eval_str("sqrt(true)").unwrap_err().to_string(),
+        "expected a number but found boolean `true`"
+    );
+}
+
+#[test]
+fn test_eval_error_spans() {
+    let span = |src| eval_spanned(src).unwrap_err().span;
+
+    assert_eq!(span("1 + 2 / (3 - 3) * 4"), 4..15);
+    assert_eq!(span("1 + true"), 4..8);
+    assert_eq!(span("~(1 - 1)"), 0..8);
+    assert_eq!(span("fn f(x) = x % 0; 2 * f(1)"), 10..15);
+    assert_eq!(span("fn f(x) = f(x); f(1)"), 10..14);
+    assert_eq!(span("let a = 1;\nmax(a)"), 11..17);
+}
+
+#[test]
+fn test_eval_print() {
+    let program =
+        crate::parser::parse("let x = 2; print x; fn f(y) = y > x; print f(3); x * 2").unwrap();
+    let mut printed = Vec::new();
+
+    assert_eq!(
+        eval_program(&program, &Env::new(), &mut |value| printed.push(value)),
+        Ok(Some(Value::Num(4.0)))
+    );
+    assert_eq!(printed, vec![Value::Num(2.0), Value::Bool(true)]);
+}
+
+#[test]
+fn test_eval_units() {
+    let eval = |src| eval_str(src).unwrap().to_string();
+
+    assert_eq!(eval("5 m / 2 s"), "2.5 m/s");
+    assert_eq!(eval("3 kg * 9.81 m/s^2"), "29.43 N");
+    assert_eq!(eval("1 km + 300 m"), "1.3 km");
+    assert_eq!(eval("90 min - 1 h"), "30 min");
+    assert_eq!(eval("2 h / 30 min"), "4");
+    assert_eq!(eval("let v = 3 m/s; v * 4 s"), "12 m");
+    assert_eq!(eval("5 Hz * 2 s"), "10");
+    assert_eq!(eval("sqrt(16 m^2)"), "4 m");
+    assert_eq!(eval("max(1 km, 300 m)"), "1 km");
+    assert_eq!(eval("1 km == 1000 m && 1 h > 59 min"), "true");
+    assert_eq!(eval("let s = 2; 10 m / s + 1 m/s * 1 s"), "6 m");
+}
+
+#[test]
+fn test_eval_unit_errors() {
+    let error = |src| eval_str(src).unwrap_err().to_string();
+
+    assert_eq!(error("1 m + 1 s"), "cannot add `m` and `s`");
+    assert_eq!(error("2 kg - 1"), "cannot subtract `kg` and a plain number");
+    assert_eq!(error("1 m < 1 s"), "cannot compare `m` and `s`");
+    assert_eq!(
+        error("sin(1 m)"),
+        "`sin` takes plain numbers but was given `m`"
+    );
+    assert_eq!(error("(4 m) ^ 0.5"), "cannot raise `m` to the power 0.5");
+    assert_eq!(error("2 ^ 1 s"), "an exponent can't have a unit");
+    assert_eq!(error("1 m / 0 s"), "division by zero");
+    assert_eq!(
+        error("1 m^2147483647 * 1 m"),
+        "the power of `m` is too large"
+    );
+    assert_eq!(
+        error("1 / 1 s^2147483647 / 1 s"),
+        "the power of `s` is too large"
+    );
+    assert_eq!(
+        error("(1 m^2000000000) ^ 2"),
+        "the power of `m` is too large"
+    );
+    assert_eq!(
+        error("1 km^2147483647 * 1 km / 1 km"),
+        "the power of `km` is too large"
+    );
+}
+
+#[test]
+fn test_eval_lists_and_higher_order_builtins() {
+    let eval = |src| eval_str(src).unwrap().to_string();
+
+    assert_eq!(eval("[1, 2 + 3, [true]]"), "[1, 5, [true]]");
+    assert_eq!(eval("map([1, 2, 3], |x| x * x)"), "[1, 4, 9]");
+    assert_eq!(eval("filter([1, 2, 3, 4], |x| x % 2 == 0)"), "[2, 4]");
+    assert_eq!(eval("fold([1, 2, 3], 10, |acc, x| acc + x)"), "16");
+    assert_eq!(eval("apply(|a, b| a - b, [5, 2])"), "3");
+    assert_eq!(eval("len([]) + len([[1, 2], 3])"), "2");
+    assert_eq!(eval("fn sq(x) = x * x; map([2, 3], |x| sq(x))"), "[4, 9]");
+    assert_eq!(eval("map([1 m, 2 m], |d| d / 1 s)"), "[1 m/s, 2 m/s]");
+    assert_eq!(eval("[1, [2]] == [1, [2]] && [1] != [1, 1]"), "true");
+    assert_eq!(eval("let answer = || 42; answer()"), "42");
+}
+
+#[test]
+fn test_eval_closures_capture_their_scope() {
+    let eval = |src| eval_str(src).unwrap().to_string();
+
+    assert_eq!(
+        eval("fn adder(n) = |x| x + n; let n = 100; map([1, 2], adder(10))"),
+        "[11, 12]"
+    );
+    assert_eq!(eval("let k = 2; let f = |x| x * k; let k = 3; f(5)"), "10");
+    assert_eq!(eval("let twice = |f, x| f(f(x)); twice(|x| x + 3, 1)"), "7");
+    assert_eq!(eval("|a, b| a + b"), "|a, b| a + b");
+}
+
+#[test]
+fn test_eval_lambda_errors() {
+    let error = |src| eval_spanned(src).unwrap_err();
+
+    let err = error("map([1, 2], 3)");
+    assert_eq!(
+        err.error.to_string(),
+        "expected a function but found number `3`"
+    );
+    assert_eq!(err.span, 12..13);
+
+    let err = error("fold([1], 0, |x| x)");
+    assert_eq!(
+        err.error.to_string(),
+        "`|x| x` takes 1 argument(s) but 2 were given"
+    );
+    assert_eq!(
+        error("let f = |x| x; f == f").error,
+        EvalError::CompareFunctions
+    );
+    assert_eq!(
+        error("let x = 1; x(2)").error.to_string(),
+        "`x` is not a function"
+    );
+    assert_eq!(
+        error("len(1)").error.to_string(),
+        "expected a list but found number `1`"
+    );
+    assert_eq!(
+        error("let f = |x| f(x); f(1)").error.to_string(),
+        "undefined function `f`"
+    );
+    assert_eq!(
+        error("fn loop(f) = f(f); loop(|g| loop(g))").error,
+        EvalError::RecursionLimit("loop".to_string())
+    );
+}
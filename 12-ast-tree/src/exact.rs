@@ -0,0 +1,581 @@
+use crate::ast::{Digits, Expr, ExprKind, Program, Stmt};
+use crate::builtins;
+use crate::eval::{
+    self, call_host, check_arity, Binding, Env, EvalError, Function, RuntimeError, MAX_CALL_DEPTH,
+};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+use std::fmt;
+use std::rc::Rc;
+
+// Numerators and denominators are capped, so `2 ^ 2 ^ 40` fails quickly instead of
+// running out of memory.
+pub const MAX_BITS: u64 = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(BigRational),
+    Bool(bool),
+}
+
+impl Value {
+    // Errors are reported with `eval::Value`, so a mismatched number shows as a float.
+    fn approximate(&self) -> eval::Value {
+        match self {
+            Value::Num(x) => eval::Value::Num(x.to_f64().unwrap_or(f64::NAN)),
+            Value::Bool(b) => eval::Value::Bool(*b),
+        }
+    }
+
+    pub fn num(self) -> Result<BigRational, EvalError> {
+        match self {
+            Value::Num(x) => Ok(x),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: self.approximate(),
+            }),
+        }
+    }
+
+    pub fn bool(self) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            _ => Err(EvalError::TypeMismatch {
+                expected: "boolean",
+                found: self.approximate(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Style::default().format(self))
+    }
+}
+
+// How results are printed: `7/2` by default, `3 1/2` as a mixed number, and followed
+// by a decimal expansion like `≈ 3.50` when `digits` is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Style {
+    pub mixed: bool,
+    pub digits: Option<usize>,
+}
+
+impl Style {
+    pub fn format(&self, value: &Value) -> String {
+        let x = match value {
+            Value::Num(x) => x,
+            Value::Bool(b) => return b.to_string(),
+        };
+
+        let fraction = if self.mixed && !x.is_integer() && x.abs() > BigRational::one() {
+            let whole = x.trunc();
+            format!("{} {}", whole, (x - &whole).abs())
+        } else {
+            x.to_string()
+        };
+
+        match self.digits {
+            Some(digits) if !x.is_integer() => {
+                let (decimal, exact) = decimal(x, digits);
+                format!("{} {} {}", fraction, if exact { "=" } else { "≈" }, decimal)
+            }
+            _ => fraction,
+        }
+    }
+}
+
+// `x` rounded to `digits` places, and whether that is exact. Exact expansions don't
+// keep trailing zeros, and an expansion that rounds to zero has no sign.
+pub fn decimal(x: &BigRational, digits: usize) -> (String, bool) {
+    let scale = BigInt::from(10).pow(digits as u32);
+    let scaled = (x * BigRational::from_integer(scale.clone())).round();
+    let exact = &scaled / BigRational::from_integer(scale) == *x;
+
+    let digits_str = scaled.to_integer().abs().to_string();
+    let padded = format!("{:0>width$}", digits_str, width = digits + 1);
+    let (int, frac) = padded.split_at(padded.len() - digits);
+    let frac = if exact {
+        frac.trim_end_matches('0')
+    } else {
+        frac
+    };
+    let sign = if scaled.is_negative() { "-" } else { "" };
+
+    if frac.is_empty() {
+        (format!("{}{}", sign, int), exact)
+    } else {
+        (format!("{}{}.{}", sign, int, frac), exact)
+    }
+}
+
+fn checked(x: BigRational) -> Result<BigRational, EvalError> {
+    if x.numer().bits() > MAX_BITS || x.denom().bits() > MAX_BITS {
+        Err(EvalError::Overflow)
+    } else {
+        Ok(x)
+    }
+}
+
+// Literals are read from the digits they were written with, so `0.1` is exactly 1/10
+// and `9007199254740993` isn't rounded. Numbers without digits, like generated ones,
+// use the shortest decimal that reads back as the same float.
+pub fn from_f64(x: f64) -> Result<BigRational, EvalError> {
+    if !x.is_finite() {
+        return Err(EvalError::Overflow);
+    }
+
+    from_digits(&format!("{:e}", x))
+}
+
+// Reads a literal the parser accepted, or the `{:e}` form of a float.
+fn from_digits(text: &str) -> Result<BigRational, EvalError> {
+    let text = text.replace('_', "");
+
+    if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        let digits = BigInt::parse_bytes(digits.as_bytes(), 16).expect("hexadecimal digits");
+        return checked(BigRational::from_integer(digits));
+    }
+
+    let (mantissa, exponent) = text.split_once(['e', 'E']).unwrap_or((&text, "0"));
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    let digits = format!("{}{}", int, frac)
+        .parse::<BigInt>()
+        .expect("mantissa is made of digits");
+    // An exponent too large for an `i64` overflows anyway, unless the digits are zero.
+    let exponent = match exponent.parse::<i64>() {
+        Ok(exponent) => exponent - frac.len() as i64,
+        Err(_) if digits.is_zero() => 0,
+        Err(_) => return Err(EvalError::Overflow),
+    };
+
+    if digits.is_zero() {
+        return Ok(BigRational::zero());
+    }
+
+    // `10^n` has more than `n` bits, so larger exponents can't be within the cap.
+    if exponent.unsigned_abs() > MAX_BITS + digits.bits() {
+        return Err(EvalError::Overflow);
+    }
+
+    let scale = BigRational::from_integer(BigInt::from(10).pow(exponent.unsigned_abs()));
+
+    if exponent >= 0 {
+        checked(BigRational::from_integer(digits) * scale)
+    } else {
+        checked(BigRational::from_integer(digits) / scale)
+    }
+}
+
+fn divide(lhs: &BigRational, rhs: &BigRational) -> Result<BigRational, EvalError> {
+    if rhs.is_zero() {
+        Err(EvalError::DivisionByZero)
+    } else {
+        checked(lhs / rhs)
+    }
+}
+
+// `%` keeps the sign of the left operand, like it does for floats.
+fn remainder(lhs: &BigRational, rhs: &BigRational) -> Result<BigRational, EvalError> {
+    let quotient = divide(lhs, rhs)?.trunc();
+    checked(lhs - rhs * quotient)
+}
+
+// The exact `n`th root of `x`, if it is rational.
+fn root(x: &BigRational, n: u32) -> Option<BigRational> {
+    if x.is_negative() && n.is_even() {
+        return None;
+    }
+
+    let exact = |x: &BigInt| {
+        let root = x.nth_root(n);
+        (Pow::pow(&root, n) == *x).then_some(root)
+    };
+
+    Some(BigRational::new(exact(x.numer())?, exact(x.denom())?))
+}
+
+// `x ^ (p / q)` is the `q`th root of `x` raised to `p`, which is only exact when the
+// root is rational.
+fn power(base: &BigRational, exponent: &BigRational) -> Result<BigRational, EvalError> {
+    let inexact = || EvalError::Inexact(format!("{} ^ {}", operand(base), operand(exponent)));
+
+    let base = match exponent.denom().to_u32() {
+        Some(1) => base.clone(),
+        Some(q) => root(base, q).ok_or_else(inexact)?,
+        None => return Err(inexact()),
+    };
+    let p = exponent.numer();
+
+    if base.is_zero() && p.is_negative() {
+        return Err(EvalError::DivisionByZero);
+    }
+
+    // Powers of 0, 1 and -1 never grow, whatever the exponent.
+    if base.is_zero() || base.abs().is_one() {
+        return Ok(match (base.is_negative(), p.is_zero()) {
+            (_, true) => BigRational::one(),
+            (true, false) if p.is_odd() => -BigRational::one(),
+            (true, false) => BigRational::one(),
+            (false, false) => base,
+        });
+    }
+
+    let bits = base.numer().bits().max(base.denom().bits());
+    let p_abs = p
+        .abs()
+        .to_u64()
+        .filter(|p| p.saturating_mul(bits - 1) <= MAX_BITS)
+        .ok_or(EvalError::Overflow)?;
+
+    let result = Pow::pow(&base, p_abs);
+
+    checked(if p.is_negative() {
+        result.recip()
+    } else {
+        result
+    })
+}
+
+fn operand(x: &BigRational) -> String {
+    if x.is_integer() && !x.is_negative() {
+        x.to_string()
+    } else {
+        format!("({})", x)
+    }
+}
+
+// Only the built-ins that map rationals to rationals are exact, and `sqrt` only when
+// its argument is a perfect square.
+fn apply(name: &str, args: &[BigRational]) -> Result<BigRational, EvalError> {
+    let inexact = || {
+        let args = args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        EvalError::Inexact(format!("{}({})", name, args.join(", ")))
+    };
+
+    match (name, args) {
+        ("sqrt", [x]) => root(x, 2).ok_or_else(inexact),
+        ("abs", [x]) => Ok(x.abs()),
+        ("min", [a, b]) => Ok(a.min(b).clone()),
+        ("max", [a, b]) => Ok(a.max(b).clone()),
+        ("floor", [x]) => Ok(x.floor()),
+        ("ceil", [x]) => Ok(x.ceil()),
+        ("round", [x]) => Ok(x.round()),
+        _ => Err(inexact()),
+    }
+}
+
+fn equal(lhs: Value, rhs: Value) -> Result<bool, EvalError> {
+    match (lhs, rhs) {
+        (Value::Num(a), Value::Num(b)) => Ok(a == b),
+        (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+        (lhs, rhs) => Err(EvalError::TypeMismatch {
+            expected: lhs.approximate().type_name(),
+            found: rhs.approximate(),
+        }),
+    }
+}
+
+// Evaluates like `eval::eval_program`, with numbers as arbitrary-precision rationals.
+pub fn eval_program(
+    program: &Program,
+    env: &Env<Value>,
+    out: &mut dyn FnMut(Value),
+) -> Result<Option<Value>, RuntimeError> {
+    let mut env = env.clone();
+
+    for stmt in &program.stmts {
+        env = exec(stmt, &env, out)?;
+    }
+
+    program
+        .result
+        .as_ref()
+        .map(|result| eval_at(result, &env, 0))
+        .transpose()
+}
+
+fn exec(
+    stmt: &Stmt,
+    env: &Env<Value>,
+    out: &mut dyn FnMut(Value),
+) -> Result<Env<Value>, RuntimeError> {
+    match stmt {
+        Stmt::Let(name, value) => Ok(env.bind(name, eval_at(value, env, 0)?)),
+        Stmt::Fn(name, params, body) => Ok(env.bind_fn(
+            name,
+            Rc::new(Function {
+                params: params.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            }),
+        )),
+        Stmt::Print(value) => {
+            out(eval_at(value, env, 0)?);
+            Ok(env.clone())
+        }
+    }
+}
+
+fn eval_at(expr: &Expr, env: &Env<Value>, depth: usize) -> Result<Value, RuntimeError> {
+    let at = |err: EvalError| err.at(&expr.span);
+    let eval = |expr: &Expr| eval_at(expr, env, depth);
+    let num = |value: Value, expr: &Expr| value.num().map_err(|err| err.at(&expr.span));
+    let boolean = |expr: &Expr| eval(expr)?.bool().map_err(|err| err.at(&expr.span));
+    let number = |expr: &Expr| num(eval(expr)?, expr);
+    let numbers = |lhs: &Expr, rhs: &Expr| {
+        let (a, b) = (eval(lhs)?, eval(rhs)?);
+        Ok::<_, RuntimeError>((num(a, lhs)?, num(b, rhs)?))
+    };
+    let arithmetic = |lhs, rhs, op: fn(&_, &_) -> Result<BigRational, EvalError>| {
+        let (lhs, rhs) = numbers(lhs, rhs)?;
+        Ok(Value::Num(op(&lhs, &rhs).map_err(at)?))
+    };
+    let compare = |lhs, rhs, op: fn(&BigRational, &BigRational) -> bool| {
+        let (lhs, rhs) = numbers(lhs, rhs)?;
+        Ok(Value::Bool(op(&lhs, &rhs)))
+    };
+    let equal = |lhs: &Expr, rhs: &Expr| {
+        let (a, b) = (eval(lhs)?, eval(rhs)?);
+        equal(a, b).map_err(|err| err.at(&rhs.span))
+    };
+
+    match &expr.kind {
+        ExprKind::Num(x, Digits(digits)) => Ok(Value::Num(
+            match digits {
+                Some(digits) => from_digits(digits),
+                None => from_f64(*x),
+            }
+            .map_err(at)?,
+        )),
+        ExprKind::Quantity(_, _) => Err(at(EvalError::Units(
+            "units are not supported by exact evaluation".to_string(),
+        ))),
+        ExprKind::Lambda(_, _) | ExprKind::List(_) => Err(at(EvalError::Unsupported(
+            "lambdas and lists are not supported by exact evaluation".to_string(),
+        ))),
+        ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+        ExprKind::Var(name) => match env.get(name) {
+            Some(Binding::Value(x)) => Ok(x.clone()),
+            Some(Binding::Function(_) | Binding::Host(_)) => {
+                Err(at(EvalError::NotAValue(name.clone())))
+            }
+            None if builtins::constant(name).is_some() => Err(at(EvalError::Inexact(name.clone()))),
+            None => Err(at(EvalError::UndefinedVariable(name.clone()))),
+        },
+        ExprKind::Call(name, args) => {
+            let function = match env.get(name) {
+                Some(Binding::Function(function)) => function.clone(),
+                Some(Binding::Host(host)) => {
+                    let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+                    return call_host(expr, name, host, values);
+                }
+                Some(Binding::Value(_)) => return Err(at(EvalError::NotAFunction(name.clone()))),
+                None if builtins::higher_order(name).is_some() => {
+                    let err = format!("`{}` is not supported by exact evaluation", name);
+                    return Err(at(EvalError::Unsupported(err)));
+                }
+                None => {
+                    let (_, builtin) = builtins::builtin(name)
+                        .ok_or_else(|| at(EvalError::UndefinedFunction(name.clone())))?;
+
+                    let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+                    check_arity(name, builtin.arity, values.len()).map_err(at)?;
+
+                    let values = values
+                        .into_iter()
+                        .zip(args)
+                        .map(|(value, arg)| num(value, arg))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    return Ok(Value::Num(apply(name, &values).map_err(at)?));
+                }
+            };
+
+            call(expr, name, &function, args, env, depth)
+        }
+        ExprKind::Negative(x) => Ok(Value::Num(-number(x)?)),
+        ExprKind::Invert(x) => Ok(Value::Num(
+            divide(&BigRational::one(), &number(x)?).map_err(at)?,
+        )),
+        ExprKind::Not(x) => Ok(Value::Bool(!boolean(x)?)),
+
+        ExprKind::Add(lhs, rhs) => arithmetic(lhs, rhs, |a, b| checked(a + b)),
+        ExprKind::Sub(lhs, rhs) => arithmetic(lhs, rhs, |a, b| checked(a - b)),
+        ExprKind::Mult(lhs, rhs) => arithmetic(lhs, rhs, |a, b| checked(a * b)),
+        ExprKind::Div(lhs, rhs) => arithmetic(lhs, rhs, divide),
+        ExprKind::Mod(lhs, rhs) => arithmetic(lhs, rhs, remainder),
+        ExprKind::Pow(lhs, rhs) => arithmetic(lhs, rhs, power),
+
+        ExprKind::Eq(lhs, rhs) => Ok(Value::Bool(equal(lhs, rhs)?)),
+        ExprKind::NotEq(lhs, rhs) => Ok(Value::Bool(!equal(lhs, rhs)?)),
+        ExprKind::Less(lhs, rhs) => compare(lhs, rhs, BigRational::lt),
+        ExprKind::LessEq(lhs, rhs) => compare(lhs, rhs, BigRational::le),
+        ExprKind::Greater(lhs, rhs) => compare(lhs, rhs, BigRational::gt),
+        ExprKind::GreaterEq(lhs, rhs) => compare(lhs, rhs, BigRational::ge),
+        ExprKind::And(lhs, rhs) => Ok(Value::Bool(boolean(lhs)? && boolean(rhs)?)),
+        ExprKind::Or(lhs, rhs) => Ok(Value::Bool(boolean(lhs)? || boolean(rhs)?)),
+
+        ExprKind::If(cond, then, otherwise) => {
+            if boolean(cond)? {
+                eval(then)
+            } else {
+                eval(otherwise)
+            }
+        }
+    }
+}
+
+fn call(
+    expr: &Expr,
+    name: &str,
+    function: &Rc<Function<Value>>,
+    args: &[Expr],
+    env: &Env<Value>,
+    depth: usize,
+) -> Result<Value, RuntimeError> {
+    let values = args
+        .iter()
+        .map(|arg| eval_at(arg, env, depth))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    check_arity(name, function.params.len(), values.len()).map_err(|err| err.at(&expr.span))?;
+
+    if depth >= MAX_CALL_DEPTH {
+        return Err(EvalError::RecursionLimit(name.to_string()).at(&expr.span));
+    }
+
+    let mut scope = function.env.bind_fn(name, function.clone());
+
+    for (param, value) in function.params.iter().zip(values) {
+        scope = scope.bind(param, value);
+    }
+
+    eval_at(&function.body, &scope, depth + 1)
+}
+
+#[cfg(test)]
+fn eval_str(src: &str) -> Result<String, EvalError> {
+    eval_program(
+        &crate::parser::parse(src).unwrap(),
+        &Env::new(),
+        &mut |_| {},
+    )
+    .map(|value| value.unwrap().to_string())
+    .map_err(|err| err.error)
+}
+
+#[test]
+fn test_exact_arithmetic() {
+    assert_eq!(eval_str("2 / 3"), Ok("2/3".to_string()));
+    assert_eq!(eval_str("1 / 3 + 1 / 6"), Ok("1/2".to_string()));
+    assert_eq!(eval_str("0.1 + 0.2 == 0.3"), Ok("true".to_string()));
+    assert_eq!(eval_str("~(3 / 4) - 1.5e1"), Ok("-41/3".to_string()));
+    assert_eq!(eval_str("-7 / 2 % 2"), Ok("-3/2".to_string()));
+    assert_eq!(
+        eval_str("if 1 / 3 < 0.3334 then 1 else 0"),
+        Ok("1".to_string())
+    );
+}
+
+#[test]
+fn test_exact_literals_keep_their_digits() {
+    assert_eq!(
+        eval_str("9007199254740993 - 9007199254740992"),
+        Ok("1".to_string())
+    );
+    assert_eq!(eval_str("1e-400 * 10 ^ 400"), Ok("1".to_string()));
+    assert_eq!(
+        eval_str("0x1_F + 1_000.000_1"),
+        Ok("10310001/10000".to_string())
+    );
+    assert_eq!(eval_str("0e99999999999999999999"), Ok("0".to_string()));
+    assert_eq!(eval_str("1e-99999"), Err(EvalError::Overflow));
+}
+
+#[test]
+fn test_exact_big_integers() {
+    assert_eq!(
+        eval_str("fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); fact(30)"),
+        Ok("265252859812191058636308480000000".to_string())
+    );
+    assert_eq!(
+        eval_str("2 ^ 100 + 1"),
+        Ok("1267650600228229401496703205377".to_string())
+    );
+}
+
+#[test]
+fn test_exact_powers_and_builtins() {
+    assert_eq!(eval_str("(4 / 9) ^ -1.5"), Ok("27/8".to_string()));
+    assert_eq!(eval_str("(-8) ^ (1 / 3)"), Ok("-2".to_string()));
+    assert_eq!(eval_str("sqrt(0.25) + abs(-1 / 3)"), Ok("5/6".to_string()));
+    assert_eq!(
+        eval_str("round(5 / 2) + floor(-1 / 2) + max(1 / 3, 0.3)"),
+        Ok("7/3".to_string())
+    );
+    assert_eq!(
+        eval_str("(-1) ^ (2 ^ 80 + 1) + 1 ^ (10 ^ 30)"),
+        Ok("0".to_string())
+    );
+}
+
+#[test]
+fn test_exact_errors() {
+    assert_eq!(
+        eval_str("1 / (1 / 3 - 2 / 6)"),
+        Err(EvalError::DivisionByZero)
+    );
+    assert_eq!(eval_str("0 ^ -1"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval_str("2 ^ 2 ^ 20"), Err(EvalError::Overflow));
+    assert_eq!(eval_str("1e300 ^ 300"), Err(EvalError::Overflow));
+    assert_eq!(
+        eval_str("sqrt(2)"),
+        Err(EvalError::Inexact("sqrt(2)".to_string()))
+    );
+    assert_eq!(
+        eval_str("2 ^ (1 / 2)"),
+        Err(EvalError::Inexact("2 ^ (1/2)".to_string()))
+    );
+    assert_eq!(eval_str("pi"), Err(EvalError::Inexact("pi".to_string())));
+    assert_eq!(
+        eval_str("sin(0)"),
+        Err(EvalError::Inexact("sin(0)".to_string()))
+    );
+}
+
+#[test]
+fn test_exact_formatting() {
+    let value = |src| {
+        eval_program(
+            &crate::parser::parse(src).unwrap(),
+            &Env::new(),
+            &mut |_| {},
+        )
+        .unwrap()
+        .unwrap()
+    };
+    let mixed = Style {
+        mixed: true,
+        digits: None,
+    };
+    let digits = Style {
+        mixed: false,
+        digits: Some(4),
+    };
+
+    assert_eq!(mixed.format(&value("7 / 2")), "3 1/2");
+    assert_eq!(mixed.format(&value("-7 / 2")), "-3 1/2");
+    assert_eq!(mixed.format(&value("-1 / 2")), "-1/2");
+    assert_eq!(mixed.format(&value("4")), "4");
+    assert_eq!(digits.format(&value("2 / 3")), "2/3 ≈ 0.6667");
+    assert_eq!(digits.format(&value("-1 / 8")), "-1/8 = -0.125");
+    assert_eq!(digits.format(&value("-1 / 30000")), "-1/30000 ≈ 0.0000");
+    assert_eq!(digits.format(&value("-1 / 20000")), "-1/20000 ≈ -0.0001");
+    assert_eq!(digits.format(&value("12")), "12");
+    assert_eq!(digits.format(&value("true")), "true");
+}
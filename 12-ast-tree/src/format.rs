@@ -0,0 +1,431 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::lexer::{self, Token};
+use crate::units;
+
+const IF: u8 = 0;
+const OR: u8 = 1;
+const AND: u8 = 2;
+const COMPARE: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const UNARY: u8 = 6;
+const POWER: u8 = 7;
+const ATOM: u8 = 8;
+
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        // `2 m ^ 2` would square the unit, so quantities are parenthesized like `-2` is.
+        ExprKind::Num(x, _) if x.is_sign_negative() => UNARY,
+        ExprKind::Quantity(_, _) => UNARY,
+        ExprKind::Num(_, _)
+        | ExprKind::Bool(_)
+        | ExprKind::Var(_)
+        | ExprKind::Call(_, _)
+        | ExprKind::List(_) => ATOM,
+        ExprKind::Negative(_) | ExprKind::Invert(_) | ExprKind::Not(_) => UNARY,
+        ExprKind::Pow(_, _) => POWER,
+        ExprKind::Mult(_, _) | ExprKind::Div(_, _) | ExprKind::Mod(_, _) => PRODUCT,
+        ExprKind::Add(_, _) | ExprKind::Sub(_, _) => SUM,
+        ExprKind::Eq(_, _)
+        | ExprKind::NotEq(_, _)
+        | ExprKind::Less(_, _)
+        | ExprKind::LessEq(_, _)
+        | ExprKind::Greater(_, _)
+        | ExprKind::GreaterEq(_, _) => COMPARE,
+        ExprKind::And(_, _) => AND,
+        ExprKind::Or(_, _) => OR,
+        // An `if` extends as far right as possible, so it is parenthesized as an operand.
+        // So is the body of a lambda.
+        ExprKind::If(_, _, _) | ExprKind::Lambda(_, _) => IF,
+    }
+}
+
+pub fn format_number(x: f64) -> String {
+    if x.fract() == 0.0 && x.abs() < 1e16 {
+        format!("{}", x)
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+pub fn format_expr(expr: &Expr) -> String {
+    match &expr.kind {
+        ExprKind::Num(x, _) => format_number(*x),
+        ExprKind::Quantity(x, unit) => {
+            format!("{} {}", format_number(*x), units::format_unit(unit))
+        }
+        ExprKind::Bool(b) => b.to_string(),
+        ExprKind::Var(name) => name.clone(),
+        ExprKind::Call(name, args) => format!("{}({})", name, list(args.iter().map(format_expr))),
+        ExprKind::Negative(x) => format!("-{}", operand(x, UNARY)),
+        ExprKind::Invert(x) => format!("~{}", operand(x, UNARY)),
+        ExprKind::Not(x) => format!("!{}", operand(x, UNARY)),
+
+        ExprKind::Add(lhs, rhs) => binary(lhs, "+", rhs, SUM),
+        ExprKind::Sub(lhs, rhs) => binary(lhs, "-", rhs, SUM),
+        ExprKind::Mult(lhs, rhs) => product(lhs, "*", rhs),
+        ExprKind::Div(lhs, rhs) => product(lhs, "/", rhs),
+        ExprKind::Mod(lhs, rhs) => binary(lhs, "%", rhs, PRODUCT),
+        // `^` is right associative and takes unary operators on its right: `2 ^ -x ^ 2`.
+        ExprKind::Pow(lhs, rhs) => format!("{} ^ {}", operand(lhs, ATOM), operand(rhs, UNARY)),
+
+        ExprKind::Eq(lhs, rhs) => comparison(lhs, "==", rhs),
+        ExprKind::NotEq(lhs, rhs) => comparison(lhs, "!=", rhs),
+        ExprKind::Less(lhs, rhs) => comparison(lhs, "<", rhs),
+        ExprKind::LessEq(lhs, rhs) => comparison(lhs, "<=", rhs),
+        ExprKind::Greater(lhs, rhs) => comparison(lhs, ">", rhs),
+        ExprKind::GreaterEq(lhs, rhs) => comparison(lhs, ">=", rhs),
+        ExprKind::And(lhs, rhs) => binary(lhs, "&&", rhs, AND),
+        ExprKind::Or(lhs, rhs) => binary(lhs, "||", rhs, OR),
+
+        ExprKind::If(cond, then, otherwise) => format!(
+            "if {} then {} else {}",
+            format_expr(cond),
+            format_expr(then),
+            format_expr(otherwise)
+        ),
+        // A lambda without parameters comes out as `|| x`, which lexes as a single `||`
+        // token. The parser accepts that too.
+        ExprKind::Lambda(params, body) => {
+            format!("|{}| {}", list(params.iter().cloned()), format_expr(body))
+        }
+        ExprKind::List(items) => format!("[{}]", list(items.iter().map(format_expr))),
+    }
+}
+
+// Binary operators are left associative, so a right operand of the same precedence
+// still needs parentheses: `a - (b - c)`.
+fn binary(lhs: &Expr, op: &str, rhs: &Expr, prec: u8) -> String {
+    format!("{} {} {}", operand(lhs, prec), op, operand(rhs, prec + 1))
+}
+
+// A unit name after a quantity would be read as part of its unit, so `(2 m) * s`
+// keeps its parentheses when `s` is a variable.
+fn product(lhs: &Expr, op: &str, rhs: &Expr) -> String {
+    if ends_with_quantity(lhs) && leading_name(rhs).is_some_and(units::is_unit) {
+        format!(
+            "({}) {} {}",
+            format_expr(lhs),
+            op,
+            operand(rhs, PRODUCT + 1)
+        )
+    } else {
+        binary(lhs, op, rhs, PRODUCT)
+    }
+}
+
+fn ends_with_quantity(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Quantity(_, _) => true,
+        ExprKind::Negative(x) | ExprKind::Invert(x) | ExprKind::Not(x) => {
+            precedence(x) >= UNARY && ends_with_quantity(x)
+        }
+        ExprKind::Mult(_, rhs) | ExprKind::Div(_, rhs) | ExprKind::Mod(_, rhs) => {
+            precedence(rhs) > PRODUCT && ends_with_quantity(rhs)
+        }
+        ExprKind::Pow(_, rhs) => precedence(rhs) >= UNARY && ends_with_quantity(rhs),
+        _ => false,
+    }
+}
+
+fn leading_name(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Var(name) | ExprKind::Call(name, _) => Some(name),
+        ExprKind::Pow(lhs, _) => leading_name(lhs),
+        _ => None,
+    }
+}
+
+// Comparisons don't chain, so neither side can be another comparison.
+fn comparison(lhs: &Expr, op: &str, rhs: &Expr) -> String {
+    format!(
+        "{} {} {}",
+        operand(lhs, COMPARE + 1),
+        op,
+        operand(rhs, COMPARE + 1)
+    )
+}
+
+fn operand(expr: &Expr, min_prec: u8) -> String {
+    if precedence(expr) < min_prec {
+        format!("({})", format_expr(expr))
+    } else {
+        format_expr(expr)
+    }
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+pub fn format_stmt(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::Let(name, value) => format!("let {} = {};", name, format_expr(value)),
+        Stmt::Fn(name, params, body) => format!(
+            "fn {}({}) = {};",
+            name,
+            list(params.iter().cloned()),
+            format_expr(body)
+        ),
+        Stmt::Print(value) => format!("print {};", format_expr(value)),
+    }
+}
+
+pub fn format_program(program: &Program) -> String {
+    let mut out = String::new();
+
+    for stmt in &program.stmts {
+        out += &format_stmt(stmt);
+        out += "\n";
+    }
+
+    match &program.result {
+        Some(result) => out + &format_expr(result) + "\n",
+        None => out,
+    }
+}
+
+// Like `format_program`, keeping the comments of `src`, which `program` was parsed
+// from. A comment after a statement on the same line stays at the end of that line,
+// any other comment goes on its own line before the statement it is in front of. The
+// tree has no place for comments inside an expression, so they move up too.
+pub fn format_source(program: &Program, src: &str) -> String {
+    let (tokens, comments) = lexer::lex_with_comments(src).unwrap_or_default();
+    let mut lines = program.stmts.iter().map(format_stmt).collect::<Vec<_>>();
+
+    // Where each statement starts and ends, `;` included, followed by the result.
+    let mut starts = Vec::new();
+    let mut ends = Vec::new();
+
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        if i == 0 || tokens[i - 1].0 == Token::Op(";") {
+            starts.push(span.start);
+        }
+
+        if *token == Token::Op(";") {
+            ends.push(span.end);
+        }
+    }
+
+    if let Some(result) = &program.result {
+        lines.push(format_expr(result));
+        ends.extend(tokens.last().map(|(_, span)| span.end));
+    }
+
+    // The comments before each line, and after the last one, and those at their ends.
+    let mut before = vec![Vec::new(); lines.len() + 1];
+    let mut after = vec![Vec::new(); lines.len()];
+
+    for span in comments {
+        let text = src[span.clone()].trim_end();
+        let next = ends.partition_point(|&end| end <= span.start);
+
+        match next.checked_sub(1) {
+            Some(prev)
+                if starts.get(next).is_none_or(|&start| span.start < start)
+                    && !src[ends[prev]..span.start].contains('\n') =>
+            {
+                after[prev].push(text)
+            }
+            _ => before[next].push(text),
+        }
+    }
+
+    let mut out = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        for comment in &before[i] {
+            out += comment;
+            out += "\n";
+        }
+
+        out += line;
+
+        for comment in &after[i] {
+            out += " ";
+            out += comment;
+        }
+
+        out += "\n";
+    }
+
+    for comment in &before[lines.len()] {
+        out += comment;
+        out += "\n";
+    }
+
+    out
+}
+
+#[cfg(test)]
+fn assert_round_trip(program: &Program) {
+    let src = format_program(program);
+
+    assert_eq!(&crate::parser::parse(&src).unwrap(), program, "{}", src);
+}
+
+#[cfg(test)]
+fn reformat(src: &str) -> String {
+    format_program(&crate::parser::parse(src).unwrap())
+}
+
+#[test]
+fn test_format_uses_minimal_parentheses() {
+    assert_eq!(reformat("((1 + 2)) + (3 * 4)"), "1 + 2 + 3 * 4\n");
+    assert_eq!(reformat("(1 + 2) * 3"), "(1 + 2) * 3\n");
+    assert_eq!(reformat("1 - (2 - 3)"), "1 - (2 - 3)\n");
+    assert_eq!(reformat("1 / (2 * 3)"), "1 / (2 * 3)\n");
+    assert_eq!(reformat("-(x) * ~(y + 1)"), "-x * ~(y + 1)\n");
+    assert_eq!(reformat("- - ~ 2"), "--~2\n");
+    assert_eq!(reformat("(2 ^ 3) ^ (4 ^ 5)"), "(2 ^ 3) ^ 4 ^ 5\n");
+    assert_eq!(reformat("(-2) ^ (-x) % (3 % 4)"), "(-2) ^ -x % (3 % 4)\n");
+    assert_eq!(reformat("-(2 ^ 2) * (-2) ^ 2"), "-2 ^ 2 * (-2) ^ 2\n");
+    assert_eq!(
+        reformat("(a < b) == (c || d && e)"),
+        "(a < b) == (c || d && e)\n"
+    );
+    assert_eq!(reformat("(a || b) && !(x > 1)"), "(a || b) && !(x > 1)\n");
+    assert_eq!(
+        reformat("(if a then 1 else 2) + (if b then 3 else 4 + 5)"),
+        "(if a then 1 else 2) + (if b then 3 else 4 + 5)\n"
+    );
+    assert_eq!(
+        reformat("if (if a then b else c) then (x) else if d then 1 else 2"),
+        "if if a then b else c then x else if d then 1 else 2\n"
+    );
+}
+
+#[test]
+fn test_format_numbers() {
+    assert_eq!(reformat("1_000"), "1000\n");
+    assert_eq!(reformat("0xff"), "255\n");
+    assert_eq!(reformat(".5"), "0.5\n");
+    assert_eq!(reformat("1e-9"), "1e-9\n");
+    assert_eq!(reformat("1e20"), "1e20\n");
+}
+
+#[test]
+fn test_format_units() {
+    assert_eq!(reformat("9.81   m/s ^2"), "9.81 m/s^2\n");
+    assert_eq!(reformat("9.81 m / s^2"), "(9.81 m) / s ^ 2\n");
+    assert_eq!(reformat("(2 m)*(s)"), "(2 m) * s\n");
+    assert_eq!(reformat("2 m*3"), "2 m * 3\n");
+}
+
+#[test]
+fn test_format_statements() {
+    assert_eq!(
+        reformat("let   rate=3;let n =12 ;\n\n rate*n"),
+        "let rate = 3;\nlet n = 12;\nrate * n\n"
+    );
+}
+
+#[test]
+fn test_format_functions() {
+    assert_eq!(
+        reformat("fn  f( a,b )=a*b;fn g()=1;f(g( ) ,2)"),
+        "fn f(a, b) = a * b;\nfn g() = 1;\nf(g(), 2)\n"
+    );
+}
+
+#[test]
+fn test_format_lambdas_and_lists() {
+    assert_eq!(
+        reformat("map( [1,2 ,], | x |x*2 )"),
+        "map([1, 2], |x| x * 2)\n"
+    );
+    assert_eq!(reformat("(|x| x) + [ ]"), "(|x| x) + []\n");
+    assert_eq!(reformat("||(1 + 2)"), "|| 1 + 2\n");
+}
+
+#[test]
+fn test_format_source_keeps_comments() {
+    let src = "# rates\nlet rate=3 ;  // per hour\n\n/* two\n lines */ let n = rate*(2 /* h */ + 1);\nfn f(x)=x; /* a */ // b\nprint n ;\nn + f(1) // total\n// end\n";
+    let formatted = format_source(&crate::parser::parse(src).unwrap(), src);
+
+    assert_eq!(
+        formatted,
+        concat!(
+            "# rates\n",
+            "let rate = 3; // per hour\n",
+            "/* two\n lines */\n",
+            "/* h */\n",
+            "let n = rate * (2 + 1);\n",
+            "fn f(x) = x; /* a */ // b\n",
+            "print n;\n",
+            "n + f(1) // total\n",
+            "// end\n",
+        )
+    );
+    assert_eq!(crate::parser::parse(&formatted), crate::parser::parse(src));
+    assert_eq!(
+        format_source(&crate::parser::parse(&formatted).unwrap(), &formatted),
+        formatted
+    );
+    assert_eq!(
+        format_source(
+            &crate::parser::parse("print 1; // one\n# done").unwrap(),
+            "print 1; // one\n# done"
+        ),
+        "print 1; // one\n# done\n"
+    );
+}
+
+#[test]
+fn test_format_print_drops_comments() {
+    assert_eq!(
+        reformat("// area\nlet a=2*3;print  a ;/* done */a"),
+        "let a = 2 * 3;\nprint a;\na\n"
+    );
+}
+
+#[test]
+fn test_format_negative_literal_keeps_tree() {
+    let program = Program {
+        stmts: vec![],
+        result: Some(
+            ExprKind::Sub(
+                Box::new(ExprKind::num(1.0).into()),
+                Box::new(ExprKind::Negative(Box::new(ExprKind::num(-2.0).into())).into()),
+            )
+            .into(),
+        ),
+    };
+
+    assert_eq!(format_program(&program), "1 - --2\n");
+}
+
+#[test]
+fn test_format_round_trip() {
+    for src in [
+        "1 + ~1234 - 2 / 3",
+        "let rate = 3; let n = 12; rate * n",
+        "fn f(a, b) = a * (b - 1); fn g() = -f(1, 2); f(g(), (3)) * ~f(1 + 2, 3)",
+        "(1 + 2) * (3 - (4 - 5)) / -(6 / ~7)",
+        "1 - 2 - 3 + (4 + 5)",
+        "0.1 + 1e-300 * 123456789012345680000",
+        "let x = 1; print x; fn f(a) = a > x; print f(2) || false; x",
+        "let g = 9.81 m/s^2; 3 kg * g + (2 m) * x * 1 s^-2 / ~(4 N)",
+        "let f = |a, b| if a then b else || [a, [b], []]; fold([1, 2], 0, |acc, x| acc + x)",
+        "print 1; print 2;",
+    ] {
+        assert_round_trip(&crate::parser::parse(src).unwrap());
+    }
+}
+
+#[test]
+fn test_format_round_trip_generated() {
+    let mut seed = 42;
+
+    for _ in 0..500 {
+        let program = Program {
+            stmts: vec![Stmt::Let(
+                "x".to_string(),
+                crate::bench::generate(&mut seed, 3),
+            )],
+            result: Some(crate::bench::generate(&mut seed, 6)),
+        };
+
+        assert_round_trip(&program);
+    }
+}
@@ -0,0 +1,477 @@
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt};
+use crate::units;
+use serde_json::{json, Value};
+use std::fmt;
+
+// The JSON form of a program, as written by `--emit=json` and read back by `import`:
+//
+//     program: {"stmts": [stmt, ...], "result": expr | null}
+//     stmt:    {"kind": "let", "name": "x", "value": expr}
+//              {"kind": "fn", "name": "f", "params": ["a", ...], "body": expr}
+//              {"kind": "print", "value": expr}
+//     expr:    {"kind": "num", "value": 1.5, "span": [start, end]}
+//              {"kind": "quantity", "value": 9.81, "unit": [["m", 1], ["s", -2]], ...}
+//              {"kind": "bool", "value": true, ...}
+//              {"kind": "var", "name": "x", ...}
+//              {"kind": "call", "name": "f", "args": [expr, ...], ...}
+//              {"kind": "negative" | "invert" | "not", "operand": expr, ...}
+//              {"kind": "add" | "sub" | ..., "lhs": expr, "rhs": expr, ...}
+//              {"kind": "if", "cond": expr, "then": expr, "else": expr, ...}
+//              {"kind": "lambda", "params": ["x", ...], "body": expr, ...}
+//              {"kind": "list", "items": [expr, ...], ...}
+//
+// Spans are byte offsets into the source. They are optional on import, nodes without
+// one get an empty span like generated nodes do.
+
+type Unary = fn(Box<Expr>) -> ExprKind;
+type Binary = fn(Box<Expr>, Box<Expr>) -> ExprKind;
+
+const UNARY: [(&str, Unary); 3] = [
+    ("negative", ExprKind::Negative),
+    ("invert", ExprKind::Invert),
+    ("not", ExprKind::Not),
+];
+
+const BINARY: [(&str, Binary); 14] = [
+    ("add", ExprKind::Add),
+    ("sub", ExprKind::Sub),
+    ("mult", ExprKind::Mult),
+    ("div", ExprKind::Div),
+    ("mod", ExprKind::Mod),
+    ("pow", ExprKind::Pow),
+    ("eq", ExprKind::Eq),
+    ("not_eq", ExprKind::NotEq),
+    ("less", ExprKind::Less),
+    ("less_eq", ExprKind::LessEq),
+    ("greater", ExprKind::Greater),
+    ("greater_eq", ExprKind::GreaterEq),
+    ("and", ExprKind::And),
+    ("or", ExprKind::Or),
+];
+
+pub fn to_json(program: &Program) -> Value {
+    let stmts = program
+        .stmts
+        .iter()
+        .map(|stmt| match stmt {
+            Stmt::Let(name, value) => json!({
+                "kind": "let",
+                "name": name,
+                "value": expr_to_json(value),
+            }),
+            Stmt::Fn(name, params, body) => json!({
+                "kind": "fn",
+                "name": name,
+                "params": params,
+                "body": expr_to_json(body),
+            }),
+            Stmt::Print(value) => json!({
+                "kind": "print",
+                "value": expr_to_json(value),
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "stmts": stmts,
+        "result": program.result.as_ref().map(expr_to_json),
+    })
+}
+
+pub fn expr_to_json(expr: &Expr) -> Value {
+    let binary = |kind: &str, lhs: &Expr, rhs: &Expr| {
+        json!({
+            "kind": kind,
+            "lhs": expr_to_json(lhs),
+            "rhs": expr_to_json(rhs),
+        })
+    };
+
+    let mut node = match &expr.kind {
+        ExprKind::Num(x, _) => json!({ "kind": "num", "value": x }),
+        ExprKind::Quantity(x, unit) => json!({ "kind": "quantity", "value": x, "unit": unit }),
+        ExprKind::Bool(b) => json!({ "kind": "bool", "value": b }),
+        ExprKind::Var(name) => json!({ "kind": "var", "name": name }),
+        ExprKind::Call(name, args) => json!({
+            "kind": "call",
+            "name": name,
+            "args": args.iter().map(expr_to_json).collect::<Vec<_>>(),
+        }),
+        ExprKind::Negative(x) => json!({ "kind": "negative", "operand": expr_to_json(x) }),
+        ExprKind::Invert(x) => json!({ "kind": "invert", "operand": expr_to_json(x) }),
+        ExprKind::Not(x) => json!({ "kind": "not", "operand": expr_to_json(x) }),
+
+        ExprKind::Add(lhs, rhs) => binary("add", lhs, rhs),
+        ExprKind::Sub(lhs, rhs) => binary("sub", lhs, rhs),
+        ExprKind::Mult(lhs, rhs) => binary("mult", lhs, rhs),
+        ExprKind::Div(lhs, rhs) => binary("div", lhs, rhs),
+        ExprKind::Mod(lhs, rhs) => binary("mod", lhs, rhs),
+        ExprKind::Pow(lhs, rhs) => binary("pow", lhs, rhs),
+        ExprKind::Eq(lhs, rhs) => binary("eq", lhs, rhs),
+        ExprKind::NotEq(lhs, rhs) => binary("not_eq", lhs, rhs),
+        ExprKind::Less(lhs, rhs) => binary("less", lhs, rhs),
+        ExprKind::LessEq(lhs, rhs) => binary("less_eq", lhs, rhs),
+        ExprKind::Greater(lhs, rhs) => binary("greater", lhs, rhs),
+        ExprKind::GreaterEq(lhs, rhs) => binary("greater_eq", lhs, rhs),
+        ExprKind::And(lhs, rhs) => binary("and", lhs, rhs),
+        ExprKind::Or(lhs, rhs) => binary("or", lhs, rhs),
+
+        ExprKind::If(cond, then, otherwise) => json!({
+            "kind": "if",
+            "cond": expr_to_json(cond),
+            "then": expr_to_json(then),
+            "else": expr_to_json(otherwise),
+        }),
+        ExprKind::Lambda(params, body) => json!({
+            "kind": "lambda",
+            "params": params,
+            "body": expr_to_json(body),
+        }),
+        ExprKind::List(items) => json!({
+            "kind": "list",
+            "items": items.iter().map(expr_to_json).collect::<Vec<_>>(),
+        }),
+    };
+
+    node["span"] = json!([expr.span.start, expr.span.end]);
+    node
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+// A JSON value together with where it is in the document, for error messages like
+// `$.result.lhs.kind: expected a string`.
+struct Node<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    fn error(&self, message: impl Into<String>) -> ImportError {
+        ImportError {
+            path: self.path.clone(),
+            message: message.into(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Node<'a>> {
+        self.value.get(name).map(|value| Node {
+            value,
+            path: format!("{}.{}", self.path, name),
+        })
+    }
+
+    fn field(&self, name: &str) -> Result<Node<'a>, ImportError> {
+        if !self.value.is_object() {
+            return Err(self.error("expected an object"));
+        }
+
+        self.get(name)
+            .ok_or_else(|| self.error(format!("missing field `{}`", name)))
+    }
+
+    fn items(&self) -> Result<Vec<Node<'a>>, ImportError> {
+        let items = self
+            .value
+            .as_array()
+            .ok_or_else(|| self.error("expected an array"))?;
+
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Node {
+                value,
+                path: format!("{}[{}]", self.path, i),
+            })
+            .collect())
+    }
+
+    fn string(&self) -> Result<String, ImportError> {
+        self.value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| self.error("expected a string"))
+    }
+
+    fn number(&self) -> Result<f64, ImportError> {
+        self.value
+            .as_f64()
+            .ok_or_else(|| self.error("expected a number"))
+    }
+
+    fn boolean(&self) -> Result<bool, ImportError> {
+        self.value
+            .as_bool()
+            .ok_or_else(|| self.error("expected a boolean"))
+    }
+
+    fn unit_part(&self) -> Result<(String, i32), ImportError> {
+        let part = match self.items()?.as_slice() {
+            [name, power] => (name.string()?, power.value.as_i64()),
+            _ => return Err(self.error("expected a `[name, power]` pair")),
+        };
+
+        match part {
+            (name, _) if !units::is_unit(&name) => {
+                Err(self.error(format!("unknown unit `{}`", name)))
+            }
+            (name, Some(power)) if i32::try_from(power).is_ok() => Ok((name, power as i32)),
+            _ => Err(self.error("expected a whole number power")),
+        }
+    }
+
+    fn span(&self) -> Result<Span, ImportError> {
+        let offsets = self
+            .items()?
+            .iter()
+            .map(|node| node.value.as_u64().map(|offset| offset as usize))
+            .collect::<Option<Vec<_>>>();
+
+        match offsets.as_deref() {
+            Some(&[start, end]) if start <= end => Ok(start..end),
+            _ => Err(self.error("expected a `[start, end]` byte range")),
+        }
+    }
+}
+
+pub fn from_json(src: &str) -> Result<Program, ImportError> {
+    let value = serde_json::from_str(src).map_err(|err| ImportError {
+        path: "$".to_string(),
+        message: err.to_string(),
+    })?;
+
+    let root = Node {
+        value: &value,
+        path: "$".to_string(),
+    };
+
+    let stmts = root
+        .field("stmts")?
+        .items()?
+        .iter()
+        .map(stmt_from_json)
+        .collect::<Result<_, _>>()?;
+
+    let result = match root.field("result")? {
+        node if node.value.is_null() => None,
+        node => Some(expr_from_json(&node)?),
+    };
+
+    Ok(Program { stmts, result })
+}
+
+fn stmt_from_json(node: &Node) -> Result<Stmt, ImportError> {
+    let kind = node.field("kind")?;
+    let name = || node.field("name")?.string();
+
+    match kind.string()?.as_str() {
+        "let" => Ok(Stmt::Let(name()?, expr_from_json(&node.field("value")?)?)),
+        "fn" => {
+            let params = node
+                .field("params")?
+                .items()?
+                .iter()
+                .map(Node::string)
+                .collect::<Result<_, _>>()?;
+
+            Ok(Stmt::Fn(
+                name()?,
+                params,
+                expr_from_json(&node.field("body")?)?,
+            ))
+        }
+        "print" => Ok(Stmt::Print(expr_from_json(&node.field("value")?)?)),
+        other => Err(kind.error(format!(
+            "unknown statement kind `{}`, expected `let`, `fn` or `print`",
+            other
+        ))),
+    }
+}
+
+fn expr_from_json(node: &Node) -> Result<Expr, ImportError> {
+    let kind = node.field("kind")?;
+    let child = |name: &str| -> Result<Box<Expr>, ImportError> {
+        Ok(Box::new(expr_from_json(&node.field(name)?)?))
+    };
+
+    let span = match node.get("span") {
+        Some(span) => span.span()?,
+        None => 0..0,
+    };
+
+    let expr = match kind.string()?.as_str() {
+        "num" => ExprKind::num(node.field("value")?.number()?),
+        "quantity" => ExprKind::Quantity(
+            node.field("value")?.number()?,
+            node.field("unit")?
+                .items()?
+                .iter()
+                .map(|part| part.unit_part())
+                .collect::<Result<_, _>>()?,
+        ),
+        "bool" => ExprKind::Bool(node.field("value")?.boolean()?),
+        "var" => ExprKind::Var(node.field("name")?.string()?),
+        "call" => ExprKind::Call(
+            node.field("name")?.string()?,
+            node.field("args")?
+                .items()?
+                .iter()
+                .map(expr_from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        "if" => ExprKind::If(child("cond")?, child("then")?, child("else")?),
+        "lambda" => ExprKind::Lambda(
+            node.field("params")?
+                .items()?
+                .iter()
+                .map(Node::string)
+                .collect::<Result<_, _>>()?,
+            child("body")?,
+        ),
+        "list" => ExprKind::List(
+            node.field("items")?
+                .items()?
+                .iter()
+                .map(expr_from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        other => {
+            if let Some((_, unary)) = UNARY.iter().find(|(name, _)| *name == other) {
+                unary(child("operand")?)
+            } else if let Some((_, binary)) = BINARY.iter().find(|(name, _)| *name == other) {
+                binary(child("lhs")?, child("rhs")?)
+            } else {
+                return Err(kind.error(format!("unknown expression kind `{}`", other)));
+            }
+        }
+    };
+
+    Ok(Expr::new(expr, span))
+}
+
+#[test]
+fn test_json_shape() {
+    let program = crate::parser::parse("-x + 1.5").unwrap();
+
+    assert_eq!(
+        to_json(&program),
+        json!({
+            "stmts": [],
+            "result": {
+                "kind": "add",
+                "lhs": {
+                    "kind": "negative",
+                    "operand": { "kind": "var", "name": "x", "span": [1, 2] },
+                    "span": [0, 2],
+                },
+                "rhs": { "kind": "num", "value": 1.5, "span": [5, 8] },
+                "span": [0, 8],
+            },
+        })
+    );
+}
+
+#[test]
+fn test_json_round_trip() {
+    let src = "let r = 2; fn area(w, h) = w * h ^ 2 % 3; print r > 1; \
+               if !(r >= 1) || r != 2 then area(r, -~pi) else min(r, 1 / 2)";
+    let program = crate::parser::parse(src).unwrap();
+    let imported = from_json(&to_json(&program).to_string()).unwrap();
+
+    assert_eq!(imported, program);
+    assert_eq!(imported.result.unwrap().span, program.result.unwrap().span);
+
+    match (&imported.stmts[1], &program.stmts[1]) {
+        (Stmt::Fn(_, _, imported), Stmt::Fn(_, _, body)) => {
+            assert_eq!(imported.span, body.span)
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_json_units() {
+    let program = crate::parser::parse("2 kg*m/s^2").unwrap();
+
+    assert_eq!(
+        to_json(&program)["result"],
+        json!({"kind": "quantity", "value": 2.0, "unit": [["kg", 1], ["m", 1], ["s", -2]], "span": [0, 10]})
+    );
+    assert_eq!(from_json(&to_json(&program).to_string()), Ok(program));
+}
+
+#[test]
+fn test_json_lambdas_and_lists() {
+    let program = crate::parser::parse("|x| [x]").unwrap();
+
+    assert_eq!(
+        to_json(&program)["result"],
+        json!({
+            "kind": "lambda",
+            "params": ["x"],
+            "body": {
+                "kind": "list",
+                "items": [{ "kind": "var", "name": "x", "span": [5, 6] }],
+                "span": [4, 7],
+            },
+            "span": [0, 7],
+        })
+    );
+    assert_eq!(from_json(&to_json(&program).to_string()), Ok(program));
+}
+
+#[test]
+fn test_json_import_without_spans() {
+    let src = r#"{"stmts": [], "result": {"kind": "bool", "value": true}}"#;
+
+    assert_eq!(
+        from_json(src),
+        Ok(Program {
+            stmts: vec![],
+            result: Some(ExprKind::Bool(true).into()),
+        })
+    );
+    assert_eq!(
+        from_json(r#"{"stmts": [], "result": null}"#),
+        Ok(Program {
+            stmts: vec![],
+            result: None,
+        })
+    );
+}
+
+#[test]
+fn test_json_import_errors() {
+    let error = |src| from_json(src).unwrap_err().to_string();
+
+    assert_eq!(
+        error(r#"{"stmts": [], "result": {"kind": "add", "lhs": {"kind": "num", "value": 1}}}"#),
+        "$.result: missing field `rhs`"
+    );
+    assert_eq!(
+        error(r#"{"stmts": [{"kind": "var", "name": "x"}], "result": 1}"#),
+        "$.stmts[0].kind: unknown statement kind `var`, expected `let`, `fn` or `print`"
+    );
+    assert_eq!(
+        error(r#"{"stmts": [], "result": {"kind": "num", "value": "1"}}"#),
+        "$.result.value: expected a number"
+    );
+    assert_eq!(
+        error(r#"{"stmts": [], "result": {"kind": "num", "value": 1, "span": [3, 1]}}"#),
+        "$.result.span: expected a `[start, end]` byte range"
+    );
+    assert_eq!(
+        error(r#"{"stmts": [], "result": {"kind": "sqrt"}}"#),
+        "$.result.kind: unknown expression kind `sqrt`"
+    );
+}
@@ -0,0 +1,262 @@
+use crate::ast::Span;
+use crate::parser::ParseError;
+use chumsky::prelude::*;
+use chumsky::Stream;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Token {
+    Num(String),
+    Ident(String),
+    Let,
+    Fn,
+    Print,
+    If,
+    Then,
+    Else,
+    True,
+    False,
+    Op(&'static str),
+}
+
+pub type Tokens = Vec<(Token, Span)>;
+
+// Longer operators come first so `<=` isn't lexed as `<` followed by `=`.
+const OPERATORS: [&str; 24] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "^", "~", "!", "<", ">", "=", ";",
+    "(", ")", ",", "[", "]", "|",
+];
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Num(text) => write!(f, "number `{}`", text),
+            Token::Ident(name) => write!(f, "name `{}`", name),
+            Token::Let => write!(f, "keyword `let`"),
+            Token::Fn => write!(f, "keyword `fn`"),
+            Token::Print => write!(f, "keyword `print`"),
+            Token::If => write!(f, "keyword `if`"),
+            Token::Then => write!(f, "keyword `then`"),
+            Token::Else => write!(f, "keyword `else`"),
+            Token::True => write!(f, "keyword `true`"),
+            Token::False => write!(f, "keyword `false`"),
+            Token::Op(c) => write!(f, "`{}`", c),
+        }
+    }
+}
+
+fn number_literal() -> impl Parser<char, String, Error = ParseError<char>> + Clone {
+    let digits = filter(|c: &char| c.is_ascii_digit() || *c == '_').repeated();
+
+    let hex = just('0')
+        .chain::<char, _, _>(one_of("xX"))
+        .chain::<char, _, _>(filter(|c: &char| c.is_ascii_alphanumeric() || *c == '_').repeated());
+
+    let mantissa = filter(|c: &char| c.is_ascii_digit())
+        .chain::<char, _, _>(digits)
+        .chain::<char, _, _>(
+            just('.')
+                .chain::<char, _, _>(digits)
+                .or_not()
+                .flatten::<char, _>(),
+        )
+        .or(just('.')
+            .chain::<char, _, _>(filter(|c: &char| c.is_ascii_digit()))
+            .chain::<char, _, _>(digits));
+
+    let exponent = one_of("eE")
+        .chain::<char, _, _>(one_of("+-").or_not())
+        .chain::<char, _, _>(digits);
+
+    let decimal = mantissa.chain::<char, _, _>(exponent.or_not().flatten::<char, _>());
+
+    hex.or(decimal).collect::<String>().labelled("number")
+}
+
+pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = ParseError<char>> {
+    lexer_with_comments().map(|(tokens, _)| tokens)
+}
+
+// The tokens, and the spans of the comments between them for the formatter.
+fn lexer_with_comments() -> impl Parser<char, (Tokens, Vec<Span>), Error = ParseError<char>> {
+    let num = number_literal().map(Token::Num);
+
+    let word = text::ident().map(|name: String| match name.as_str() {
+        "let" => Token::Let,
+        "fn" => Token::Fn,
+        "print" => Token::Print,
+        "if" => Token::If,
+        "then" => Token::Then,
+        "else" => Token::Else,
+        "true" => Token::True,
+        "false" => Token::False,
+        _ => Token::Ident(name),
+    });
+
+    let op = OPERATORS
+        .iter()
+        .map(|op| just(*op).to(*op).boxed())
+        .reduce(|a, b| a.or(b).boxed())
+        .unwrap()
+        .map(Token::Op);
+
+    // `#` and `//` comments run to the end of the line, `/* */` comments can span lines
+    // but don't nest. Both are skipped like whitespace, apart from keeping their spans.
+    let line_comment = just("//")
+        .or(just("#"))
+        .then(take_until(text::newline().or(end())))
+        .ignored();
+
+    let block_comment = just("/*")
+        .map_with_span(|_, span: Span| span)
+        .then(take_until(just("*/").to(true).or(end().to(false))))
+        .validate(|(open, (_, closed)), _, emit| {
+            if !closed {
+                emit(ParseError::custom(
+                    open,
+                    "unterminated block comment".to_string(),
+                ));
+            }
+        });
+
+    let comment = line_comment
+        .or(block_comment)
+        .map_with_span(|_, span: Span| span);
+
+    let trivia = filter(|c: &char| c.is_whitespace())
+        .to(None)
+        .or(comment.map(Some))
+        .repeated()
+        .map(|comments| comments.into_iter().flatten().collect::<Vec<_>>());
+
+    let token = num
+        .or(word)
+        .or(op)
+        .map_with_span(|token, span| (token, span));
+
+    trivia
+        .clone()
+        .then(token.then(trivia).repeated())
+        .then_ignore(end())
+        .map(|(mut comments, items)| {
+            let mut tokens = Vec::new();
+
+            for (token, after) in items {
+                tokens.push(token);
+                comments.extend(after);
+            }
+
+            (tokens, comments)
+        })
+}
+
+// Spans are byte offsets into `src` rather than the char indices chumsky uses for `&str`.
+fn stream(src: &str) -> Stream<'_, char, Span, impl Iterator<Item = (char, Span)> + '_> {
+    let len = src.len();
+
+    Stream::from_iter(
+        len..len,
+        src.char_indices().map(|(i, c)| (c, i..i + c.len_utf8())),
+    )
+}
+
+pub fn lex(src: &str) -> Result<Vec<(Token, Span)>, Vec<ParseError>> {
+    lex_with_comments(src).map(|(tokens, _)| tokens)
+}
+
+// The only way to fail is a character that can't start a token, and listing every
+// token that could have started there doesn't help.
+pub fn lex_with_comments(src: &str) -> Result<(Tokens, Vec<Span>), Vec<ParseError>> {
+    lexer_with_comments().parse(stream(src)).map_err(|errs| {
+        errs.into_iter()
+            .map(|mut err| {
+                err.expected.clear();
+                err.describe()
+            })
+            .collect()
+    })
+}
+
+pub fn format_tokens(tokens: &[(Token, Span)]) -> String {
+    tokens
+        .iter()
+        .map(|(token, span)| format!("{:?} {}\n", span, token))
+        .collect()
+}
+
+#[test]
+fn test_lex_tokens_with_spans() {
+    assert_eq!(
+        lex("let x = 1.5;\n-(x)"),
+        Ok(vec![
+            (Token::Let, 0..3),
+            (Token::Ident("x".to_string()), 4..5),
+            (Token::Op("="), 6..7),
+            (Token::Num("1.5".to_string()), 8..11),
+            (Token::Op(";"), 11..12),
+            (Token::Op("-"), 13..14),
+            (Token::Op("("), 14..15),
+            (Token::Ident("x".to_string()), 15..16),
+            (Token::Op(")"), 16..17),
+        ])
+    );
+}
+
+#[test]
+fn test_lex_unknown_character_is_error() {
+    let errs = lex("1 $ 2").unwrap_err();
+
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].span, 2..3);
+    assert_eq!(errs[0].found, Some("`$`".to_string()));
+    assert_eq!(errs[0].message(), "unexpected `$`");
+}
+
+#[test]
+fn test_lex_longest_operator() {
+    let tokens = lex("if a <= !b then true else c != d")
+        .unwrap()
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        tokens,
+        vec![
+            Token::If,
+            Token::Ident("a".to_string()),
+            Token::Op("<="),
+            Token::Op("!"),
+            Token::Ident("b".to_string()),
+            Token::Then,
+            Token::True,
+            Token::Else,
+            Token::Ident("c".to_string()),
+            Token::Op("!="),
+            Token::Ident("d".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_lex_skips_comments() {
+    assert_eq!(
+        lex("# header\n1 // one\n/* two\n */ + /**/2 # end"),
+        Ok(vec![
+            (Token::Num("1".to_string()), 9..10),
+            (Token::Op("+"), 29..30),
+            (Token::Num("2".to_string()), 35..36),
+        ])
+    );
+    assert_eq!(lex("// only a comment"), Ok(vec![]));
+    assert_eq!(lex("1/2").unwrap()[1], (Token::Op("/"), 1..2));
+}
+
+#[test]
+fn test_lex_unterminated_block_comment() {
+    let errs = lex("1 + /* 2\n3").unwrap_err();
+
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].span, 4..6);
+    assert_eq!(errs[0].message(), "unterminated block comment");
+}
@@ -0,0 +1,139 @@
+//! A small expression language with `let` bindings, functions and physical units.
+//!
+//! Programs are parsed with [`parse`] and run with [`eval`](fn@eval) in an [`Env`]. The host
+//! can put its own values and functions in the environment, so formulas can refer
+//! to data that only exists at runtime:
+//!
+//! ```
+//! use ast_tree::{Env, EvalError, Value};
+//!
+//! let program = ast_tree::parse("let total = price * qty; discount(total)").unwrap();
+//! let env = Env::new()
+//!     .bind("price", Value::Num(2.5))
+//!     .bind("qty", Value::Num(4.0))
+//!     .bind_host("discount", 1, |args| match &args[0] {
+//!         Value::Num(x) if *x >= 0.0 => Ok(Value::Num(x * 0.9)),
+//!         _ => Err(EvalError::Host("expected a positive amount".to_string())),
+//!     });
+//!
+//! assert_eq!(ast_tree::eval(&program, &env), Ok(Some(Value::Num(9.0))));
+//! ```
+//!
+//! Besides [`ast`], [`eval`](mod@eval) and [`diagnostics`], the [`check`],
+//! [`format`](mod@format) and [`units`] modules are public. The other modules only
+//! exist for the `ast-tree` binary and aren't part of the API.
+
+/// The syntax tree that [`parse`] produces.
+pub mod ast;
+#[doc(hidden)]
+pub mod bench;
+mod builtins;
+#[doc(hidden)]
+pub mod c;
+/// Type checking of a program before it runs.
+pub mod check;
+#[doc(hidden)]
+pub mod derive;
+/// Errors with a span, and rendering them with the source lines they point at.
+pub mod diagnostics;
+#[doc(hidden)]
+pub mod dot;
+/// The tree-walking evaluator and the environment it runs in.
+pub mod eval;
+#[doc(hidden)]
+pub mod exact;
+/// Printing a program back as source.
+pub mod format;
+#[doc(hidden)]
+pub mod json;
+#[doc(hidden)]
+pub mod lexer;
+#[doc(hidden)]
+pub mod lsp;
+#[doc(hidden)]
+pub mod optimize;
+mod parser;
+#[doc(hidden)]
+pub mod repl;
+#[doc(hidden)]
+pub mod trace;
+/// Quantities with physical units, as held by [`Value::Quantity`].
+pub mod units;
+#[doc(hidden)]
+pub mod vm;
+
+pub use ast::{Expr, ExprKind, Program, Span, Stmt};
+pub use diagnostics::{Diagnostic, Diagnostics};
+pub use eval::{Env, EvalError, RuntimeError, Value};
+
+/// Parses a whole program: statements followed by the expression that is its result.
+///
+/// The parser recovers from errors, so all of them are reported at once. Use
+/// [`Diagnostics::render`] to show them with the offending source lines.
+pub fn parse(src: &str) -> Result<Program, Diagnostics> {
+    parser::parse(src).map_err(|errs| errs.iter().map(Diagnostic::from).collect())
+}
+
+/// Evaluates `program` in `env` and returns the value of its result, or `None` if
+/// the program ends with a statement.
+///
+/// Bindings made by the program don't leak into `env`. Values of `print` statements
+/// are dropped, [`eval::eval_program`] hands them to a callback instead. Errors carry
+/// the span of the expression that failed, and convert to a [`Diagnostic`].
+pub fn eval(program: &Program, env: &Env) -> Result<Option<Value>, RuntimeError> {
+    eval::eval_program(program, env, &mut |_| ())
+}
+
+#[test]
+fn test_parse_reports_every_error() {
+    let errs = parse("let x = ;\nx + * 2").unwrap_err();
+
+    assert_eq!(errs.0.len(), 2);
+    assert_eq!(errs.0[1].span, 14..15);
+    assert!(errs
+        .render("rules.x", "let x = ;\nx + * 2")
+        .contains("--> rules.x:2:5"));
+}
+
+#[test]
+fn test_eval_with_host_values_and_functions() {
+    let env = Env::new()
+        .bind("limit", Value::Num(10.0))
+        .bind_host("clamp", 2, |args| {
+            let (x, max) = (args[0].clone().num()?, args[1].clone().num()?);
+            Ok(Value::Num(x.min(max)))
+        });
+    let eval_str = |src| eval(&parse(src).unwrap(), &env);
+
+    assert_eq!(eval_str("clamp(3 * 4, limit)"), Ok(Some(Value::Num(10.0))));
+    assert_eq!(
+        eval_str("let limit = 20; clamp(12, limit)"),
+        Ok(Some(Value::Num(12.0)))
+    );
+    assert_eq!(
+        eval_str("clamp(1)").unwrap_err().to_string(),
+        "`clamp` takes 2 argument(s) but 1 were given"
+    );
+    assert_eq!(
+        eval_str("1 + clamp").unwrap_err().to_string(),
+        "`clamp` is a function and must be called"
+    );
+
+    let err = eval_str("1 + clamp(true, 1)").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "expected a number but found boolean `true`"
+    );
+    assert_eq!(err.span, 4..18);
+}
+
+#[test]
+fn test_host_function_errors() {
+    let env = Env::new().bind_host("fail", 0, |_| Err(EvalError::Host("no data".to_string())));
+    let program = parse("fn f(x) = x + fail(); f(1)").unwrap();
+
+    assert_eq!(
+        eval(&program, &env),
+        Err(EvalError::Host("no data".to_string()).at(&(14..20)))
+    );
+}
@@ -0,0 +1,762 @@
+use crate::ast::{Expr, ExprKind, Program, Span, Stmt};
+use crate::check;
+use crate::diagnostics::Diagnostic;
+use crate::eval::{self, Env};
+use crate::format;
+use crate::lexer::{self, Token};
+use crate::parser;
+use serde_json::{json, Value};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// A language server speaking JSON-RPC over stdio. Documents are synced in full on
+// every change, and positions are in UTF-16 code units like the protocol requires.
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+struct Document {
+    text: String,
+    // What hovers evaluate in, worked out on the first hover after a change so an
+    // expensive `let` isn't run again for every request.
+    envs: OnceCell<Vec<Option<Env>>>,
+}
+
+fn response(id: &Value, result: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+fn error(id: &Value, code: i64, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn position(src: &str, offset: usize) -> Value {
+    let before = &src[..offset.min(src.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn range(src: &str, span: &Span) -> Value {
+    json!({"start": position(src, span.start), "end": position(src, span.end)})
+}
+
+fn offset(src: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => src.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+
+    for (i, c) in src[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+
+        units += c.len_utf16();
+    }
+
+    Some(src.len())
+}
+
+fn contains(span: &Span, offset: usize) -> bool {
+    span.start <= offset && offset < span.end
+}
+
+// The smallest sub-expression under the cursor.
+fn innermost(expr: &Expr, offset: usize) -> &Expr {
+    expr.kind
+        .children()
+        .into_iter()
+        .find(|child| contains(&child.span, offset))
+        .map_or(expr, |child| innermost(child, offset))
+}
+
+// The lambdas around the cursor, innermost last.
+fn lambdas_at(expr: &Expr, offset: usize) -> Vec<&Expr> {
+    let mut lambdas = Vec::new();
+    let mut expr = expr;
+
+    loop {
+        if let ExprKind::Lambda(_, _) = &expr.kind {
+            lambdas.push(expr);
+        }
+
+        match expr
+            .kind
+            .children()
+            .into_iter()
+            .find(|child| contains(&child.span, offset))
+        {
+            Some(child) => expr = child,
+            None => return lambdas,
+        }
+    }
+}
+
+// The name of the variable or function call under the cursor, with the span of the
+// name alone.
+fn name_at(expr: &Expr, offset: usize) -> Option<(&str, Span)> {
+    let expr = innermost(expr, offset);
+
+    match &expr.kind {
+        ExprKind::Var(name) => Some((name, expr.span.clone())),
+        ExprKind::Call(name, _) => {
+            let span = expr.span.start..expr.span.start + name.len();
+            contains(&span, offset).then_some((name, span))
+        }
+        _ => None,
+    }
+}
+
+// Where each statement defines its name and parameters. The tree doesn't keep those
+// spans, so they come from the tokens: `let`, `fn` and `print` only ever start a
+// statement, and are followed by the name and for functions the parameter list.
+fn definitions(src: &str) -> Vec<(Option<Span>, Vec<Span>)> {
+    let tokens = lexer::lex(src).unwrap_or_default();
+    let mut defs = Vec::new();
+
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        let name = tokens.get(i + 1).map(|(_, span)| span.clone());
+
+        match token {
+            Token::Let => defs.push((name, Vec::new())),
+            Token::Fn => {
+                let params = tokens[i + 2..]
+                    .iter()
+                    .take_while(|(token, _)| *token != Token::Op(")"))
+                    .filter(|(token, _)| matches!(token, Token::Ident(_)))
+                    .map(|(_, span)| span.clone())
+                    .collect();
+                defs.push((name, params))
+            }
+            Token::Print => defs.push((None, Vec::new())),
+            _ => (),
+        }
+    }
+
+    defs
+}
+
+fn defined_name(stmt: &Stmt) -> Option<&str> {
+    match stmt {
+        Stmt::Let(name, _) | Stmt::Fn(name, _, _) => Some(name),
+        Stmt::Print(_) => None,
+    }
+}
+
+// Resolves the name under the cursor the way the evaluator would: parameters first,
+// then the function itself, then the closest definition before the statement.
+fn definition(src: &str, program: &Program, offset: usize) -> Option<Span> {
+    let defs = definitions(src);
+    let index = program
+        .stmts
+        .iter()
+        .position(|stmt| match stmt {
+            Stmt::Let(_, expr) | Stmt::Fn(_, _, expr) | Stmt::Print(expr) => {
+                contains(&expr.span, offset)
+            }
+        })
+        .unwrap_or(program.stmts.len());
+
+    let expr = match program.stmts.get(index) {
+        Some(Stmt::Let(_, expr) | Stmt::Fn(_, _, expr) | Stmt::Print(expr)) => expr,
+        None => program.result.as_ref()?,
+    };
+    let (name, _) = name_at(expr, offset)?;
+
+    // A lambda's parameters are between its two `|`, the first of which is where the
+    // lambda starts.
+    if let Some(lambda) = lambdas_at(expr, offset).into_iter().rev().find(|lambda| {
+        matches!(&lambda.kind, ExprKind::Lambda(params, _) if params.iter().any(|param| param == name))
+    }) {
+        return lexer::lex(src)
+            .unwrap_or_default()
+            .into_iter()
+            .skip_while(|(_, span)| span.start != lambda.span.start)
+            .skip(1)
+            .take_while(|(token, _)| *token != Token::Op("|"))
+            .find(|(token, _)| *token == Token::Ident(name.to_string()))
+            .map(|(_, span)| span);
+    }
+
+    if let Some(Stmt::Fn(function, params, _)) = program.stmts.get(index) {
+        if let Some(i) = params.iter().position(|param| param == name) {
+            return defs[index].1.get(i).cloned();
+        }
+
+        if function == name {
+            return defs[index].0.clone();
+        }
+    }
+
+    let defined = program.stmts[..index]
+        .iter()
+        .rposition(|stmt| defined_name(stmt) == Some(name))?;
+
+    defs[defined].0.clone()
+}
+
+// The environment before each statement and before the result, or `None` from the
+// first statement that fails.
+fn environments(program: &Program) -> Vec<Option<Env>> {
+    let mut envs = vec![Some(Env::new())];
+
+    for stmt in &program.stmts {
+        let env = envs.last().cloned().flatten();
+        envs.push(env.and_then(|env| eval::exec(stmt, &env, &mut |_| ()).ok()));
+    }
+
+    envs
+}
+
+// Evaluates the sub-expression under the cursor with the definitions before it.
+// Function and lambda bodies have no values for their parameters, so they get no hover.
+fn hover(program: &Program, envs: &[Option<Env>], offset: usize) -> Option<(Span, String)> {
+    let describe = |expr: &Expr, env: &Env| {
+        let in_lambda = lambdas_at(expr, offset).into_iter().any(|lambda| {
+            matches!(&lambda.kind, ExprKind::Lambda(_, body) if contains(&body.span, offset))
+        });
+        if in_lambda {
+            return None;
+        }
+
+        let expr = innermost(expr, offset);
+        let text = match eval::eval(expr, env) {
+            Ok(value) => format!("{} = {}", format::format_expr(expr), value),
+            Err(err) => format!("{} fails: {}", format::format_expr(expr), err.error),
+        };
+
+        Some((expr.span.clone(), text))
+    };
+    for (stmt, env) in program.stmts.iter().zip(envs) {
+        match stmt {
+            Stmt::Let(_, expr) | Stmt::Print(expr) if contains(&expr.span, offset) => {
+                return describe(expr, env.as_ref()?)
+            }
+            Stmt::Fn(_, _, body) if contains(&body.span, offset) => return None,
+            _ => (),
+        }
+    }
+
+    match &program.result {
+        Some(result) if contains(&result.span, offset) => describe(result, envs.last()?.as_ref()?),
+        _ => None,
+    }
+}
+
+fn diagnostics(src: &str) -> Vec<Value> {
+    let diags: Vec<Diagnostic> = match parser::parse(src) {
+        Ok(program) => match check::check_program(&program) {
+            Ok(_) => Vec::new(),
+            Err(errs) => errs.iter().map(Diagnostic::from).collect(),
+        },
+        Err(errs) => errs.iter().map(Diagnostic::from).collect(),
+    };
+
+    diags
+        .iter()
+        .map(|diag| {
+            json!({
+                "range": range(src, &diag.span),
+                "severity": 1,
+                "source": "ast-tree",
+                "message": diag.message,
+            })
+        })
+        .collect()
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Handles one message from the client and returns the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+
+        let id = match message.get("id") {
+            Some(id) if !message["method"].is_null() => id,
+            _ => return self.notify(method, params),
+        };
+
+        if self.shutdown {
+            return vec![error(
+                id,
+                INVALID_REQUEST,
+                "the server is shutting down".to_string(),
+            )];
+        }
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": {"name": "ast-tree", "version": env!("CARGO_PKG_VERSION")},
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/formatting" => self.formatting(params),
+            _ => {
+                return vec![error(
+                    id,
+                    METHOD_NOT_FOUND,
+                    format!("unknown method `{}`", method),
+                )]
+            }
+        };
+
+        vec![response(id, result)]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Changes are always the whole document, since that's the sync kind we ask for.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish(uri, Vec::new())];
+            }
+            _ => None,
+        };
+
+        match text {
+            Some(text) => {
+                let document = Document {
+                    text: text.to_string(),
+                    envs: OnceCell::new(),
+                };
+
+                self.documents.insert(uri.to_string(), document);
+                vec![publish(uri, diagnostics(text))]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // The document and the parsed program for a request, when it parses.
+    fn document(&self, params: &Value) -> Option<(&Document, Program)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+
+        parser::parse(&document.text)
+            .ok()
+            .map(|program| (document, program))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let found = self.document(params).and_then(|(document, program)| {
+            let src = &document.text;
+            let envs = document.envs.get_or_init(|| environments(&program));
+            let (span, text) = hover(&program, envs, offset(src, &params["position"])?)?;
+            Some(json!({
+                "contents": {"kind": "plaintext", "value": text},
+                "range": range(src, &span),
+            }))
+        });
+
+        found.unwrap_or(Value::Null)
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let found = self.document(params).and_then(|(document, program)| {
+            let src = &document.text;
+            let span = definition(src, &program, offset(src, &params["position"])?)?;
+            Some(json!({
+                "uri": params["textDocument"]["uri"],
+                "range": range(src, &span),
+            }))
+        });
+
+        found.unwrap_or(Value::Null)
+    }
+
+    fn formatting(&self, params: &Value) -> Value {
+        let (src, program) = match self.document(params) {
+            Some((document, program)) => (document.text.as_str(), program),
+            None => return Value::Null,
+        };
+        let formatted = format::format_source(&program, src);
+
+        if formatted == src {
+            json!([])
+        } else {
+            json!([{"range": range(src, &(0..src.len())), "newText": formatted}])
+        }
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+// Reads the body of the next message, or `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// Serves until the client sends `exit`, which succeeds only after a `shutdown`.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+
+    while let Some(body) = read_message(input)? {
+        let message: Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(output, &error(&Value::Null, PARSE_ERROR, err.to_string()))?;
+                continue;
+            }
+        };
+
+        if message["method"] == "exit" {
+            return Ok(server.shutdown);
+        }
+
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+    }
+
+    Ok(false)
+}
+
+pub fn run() -> io::Result<bool> {
+    serve(&mut io::stdin().lock(), &mut io::stdout().lock())
+}
+
+#[cfg(test)]
+fn open(server: &mut Server, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": "file:///a.x", "languageId": "x", "version": 1, "text": text}},
+    }))
+}
+
+#[cfg(test)]
+fn request(server: &mut Server, method: &str, line: u64, character: u64) -> Value {
+    let mut replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": method,
+        "params": {
+            "textDocument": {"uri": "file:///a.x"},
+            "position": {"line": line, "character": character},
+        },
+    }));
+
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["id"], 7);
+    replies.remove(0)["result"].take()
+}
+
+#[cfg(test)]
+fn message(body: Value) -> String {
+    let body = body.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[test]
+fn test_lsp_publishes_diagnostics() {
+    let mut server = Server::new();
+
+    assert_eq!(
+        open(&mut server, "let x = 1 +;\nx"),
+        vec![publish(
+            "file:///a.x",
+            vec![json!({
+                "range": {"start": {"line": 0, "character": 11}, "end": {"line": 0, "character": 12}},
+                "severity": 1,
+                "source": "ast-tree",
+                "message": "unexpected `;`, expected `!`, `(`, `-`, `~`, boolean, `if`, lambda, list, name or number",
+            })]
+        )]
+    );
+
+    let replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": {"uri": "file:///a.x", "version": 2},
+            "contentChanges": [{"text": "let x = 1;\nx + true"}],
+        },
+    }));
+    let diags = &replies[0]["params"]["diagnostics"];
+
+    assert_eq!(diags.as_array().unwrap().len(), 1);
+    assert_eq!(
+        diags[0]["range"],
+        json!({"start": {"line": 1, "character": 4}, "end": {"line": 1, "character": 8}})
+    );
+
+    assert_eq!(
+        open(&mut server, "let x = 1;\nx + 1"),
+        vec![publish("file:///a.x", Vec::new())]
+    );
+}
+
+#[test]
+fn test_lsp_hover_shows_values() {
+    let mut server = Server::new();
+    open(
+        &mut server,
+        "let rate = 3;\nfn twice(x) = 2 * x;\nlet n = if rate > 5 then 1 / 0 else 2;\ntwice(rate) + 0.5",
+    );
+
+    let hover = request(&mut server, "textDocument/hover", 3, 8);
+    assert_eq!(hover["contents"]["value"], "rate = 3");
+    assert_eq!(
+        hover["range"],
+        json!({"start": {"line": 3, "character": 6}, "end": {"line": 3, "character": 10}})
+    );
+
+    let hover = request(&mut server, "textDocument/hover", 3, 12);
+    assert_eq!(hover["contents"]["value"], "twice(rate) + 0.5 = 6.5");
+
+    let hover = request(&mut server, "textDocument/hover", 2, 27);
+    assert_eq!(hover["contents"]["value"], "1 / 0 fails: division by zero");
+
+    assert_eq!(
+        request(&mut server, "textDocument/hover", 1, 18),
+        Value::Null
+    );
+
+    // Environments are cached per version of the document.
+    open(&mut server, "let rate = 4;\nrate");
+    let hover = request(&mut server, "textDocument/hover", 1, 0);
+    assert_eq!(hover["contents"]["value"], "rate = 4");
+
+    open(&mut server, "let rate = 1 / 0;\nrate");
+    assert_eq!(
+        request(&mut server, "textDocument/hover", 1, 0),
+        Value::Null
+    );
+}
+
+#[test]
+fn test_lsp_go_to_definition() {
+    let mut server = Server::new();
+    open(
+        &mut server,
+        "let k = 2;\nfn f(x, k) = x * k + f(x);\nlet k = k + 1;\n// π\nf(k, 1) + k",
+    );
+
+    let span = |line: u64, start: u64, end: u64| {
+        json!({
+            "uri": "file:///a.x",
+            "range": {"start": {"line": line, "character": start}, "end": {"line": line, "character": end}},
+        })
+    };
+
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 17),
+        span(1, 8, 9)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 21),
+        span(1, 3, 4)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 2, 8),
+        span(0, 4, 5)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 4, 2),
+        span(2, 4, 5)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 4, 0),
+        span(1, 3, 4)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 4, 5),
+        Value::Null
+    );
+}
+
+#[test]
+fn test_lsp_formatting() {
+    let mut server = Server::new();
+    open(&mut server, "let  x=1 ;\n x*(2+3)");
+
+    assert_eq!(
+        request(&mut server, "textDocument/formatting", 0, 0),
+        json!([{
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 8}},
+            "newText": "let x = 1;\nx * (2 + 3)\n",
+        }])
+    );
+
+    open(&mut server, "let x = 1;\nx\n");
+    assert_eq!(
+        request(&mut server, "textDocument/formatting", 0, 0),
+        json!([])
+    );
+
+    open(&mut server, "// one\nlet x = 1; # set\nx\n");
+    assert_eq!(
+        request(&mut server, "textDocument/formatting", 0, 0),
+        json!([])
+    );
+
+    open(&mut server, "let x = ;\nx\n");
+    assert_eq!(
+        request(&mut server, "textDocument/formatting", 0, 0),
+        Value::Null
+    );
+}
+
+#[test]
+fn test_lsp_session() {
+    let script = [
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}}),
+        json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": "file:///b.x", "languageId": "x", "version": 1, "text": "2 * 3"}},
+        }),
+        json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/hover",
+            "params": {"textDocument": {"uri": "file:///b.x"}, "position": {"line": 0, "character": 2}},
+        }),
+        json!({"jsonrpc": "2.0", "id": 3, "method": "workspace/symbol", "params": {}}),
+        json!({"jsonrpc": "2.0", "id": 4, "method": "shutdown"}),
+        json!({"jsonrpc": "2.0", "id": 5, "method": "textDocument/hover", "params": {}}),
+        json!({"jsonrpc": "2.0", "method": "exit"}),
+    ];
+    let input = script.into_iter().map(message).collect::<String>();
+    let mut output = Vec::new();
+
+    assert!(serve(&mut input.as_bytes(), &mut output).unwrap());
+
+    let mut output = output.as_slice();
+    let mut replies = Vec::new();
+
+    while let Some(body) = read_message(&mut output).unwrap() {
+        replies.push(serde_json::from_slice::<Value>(&body).unwrap());
+    }
+
+    assert_eq!(replies.len(), 6);
+    assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+    assert_eq!(replies[1], publish("file:///b.x", Vec::new()));
+    assert_eq!(replies[2]["result"]["contents"]["value"], "2 * 3 = 6");
+    assert_eq!(replies[3]["error"]["code"], METHOD_NOT_FOUND);
+    assert_eq!(replies[4], response(&json!(4), Value::Null));
+    assert_eq!(replies[5]["error"]["code"], INVALID_REQUEST);
+
+    // Exiting without a shutdown first is a failure.
+    let input = "Content-Length: 4\r\n\r\n{no}".to_string() + &message(json!({"method": "exit"}));
+    let mut output = Vec::new();
+
+    assert!(!serve(&mut input.as_bytes(), &mut output).unwrap());
+
+    let body = read_message(&mut output.as_slice()).unwrap().unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap()["error"]["code"],
+        PARSE_ERROR
+    );
+}
+
+#[test]
+fn test_lsp_lambdas() {
+    let mut server = Server::new();
+    open(&mut server, "let x = 2;\nmap([x], |x| x * 2) + [x]");
+
+    let span = |line: u64, start: u64, end: u64| {
+        json!({
+            "uri": "file:///a.x",
+            "range": {"start": {"line": line, "character": start}, "end": {"line": line, "character": end}},
+        })
+    };
+
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 13),
+        span(1, 10, 11)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 5),
+        span(0, 4, 5)
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 23),
+        span(0, 4, 5)
+    );
+
+    assert_eq!(
+        request(&mut server, "textDocument/hover", 1, 13),
+        Value::Null
+    );
+    let hover = request(&mut server, "textDocument/hover", 1, 2);
+    assert_eq!(hover["contents"]["value"], "map([x], |x| x * 2) = [4]");
+}
@@ -1,59 +1,489 @@
-use chumsky::prelude::*;
+use ast_tree::{
+    bench, c, check, derive, diagnostics, dot, eval, exact, format, json, lexer, lsp, optimize,
+    repl, trace, vm, Diagnostic, Diagnostics, Env, Program,
+};
+use std::str::FromStr;
+use structopt::StructOpt;
 
 #[derive(Debug)]
-enum Expr {
-    Num(f64),
-    Negative(Box<Expr>),
-    Invert(Box<Expr>),
+enum Emit {
+    Tokens,
+    Bytecode,
+    Dot,
+    Json,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "bytecode" => Ok(Emit::Bytecode),
+            "dot" => Ok(Emit::Dot),
+            "json" => Ok(Emit::Json),
+            _ => Err(format!(
+                "unknown format `{}`, expected `tokens`, `bytecode`, `dot` or `json`",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("unknown format `{}`, expected `text` or `json`", s)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "ast-tree")]
+struct CliOptions {
+    #[structopt(subcommand)]
+    command: Option<Command>,
 
-    Add(Box<Expr>, Box<Expr>),
-    Sub(Box<Expr>, Box<Expr>),
-    Mult(Box<Expr>, Box<Expr>),
-    Div(Box<Expr>, Box<Expr>),
+    /// File to evaluate, starts a REPL when omitted
+    file: Option<String>,
+
+    /// Print the compiled program instead of evaluating it (tokens, bytecode, dot or json)
+    #[structopt(long)]
+    emit: Option<Emit>,
+
+    /// Evaluate with the bytecode VM instead of walking the tree
+    #[structopt(long)]
+    vm: bool,
+
+    /// Simplify the program before running it and report the rewrites that fired
+    #[structopt(long)]
+    optimize: bool,
+
+    /// Evaluate with exact rationals instead of floats
+    #[structopt(long, conflicts_with_all = &["vm", "optimize", "emit"])]
+    exact: bool,
+
+    /// Print exact results as mixed numbers, like `3 1/2`
+    #[structopt(long, requires = "exact")]
+    mixed: bool,
+
+    /// Follow exact results with their decimal expansion to <digits> places
+    #[structopt(long, requires = "exact")]
+    digits: Option<usize>,
+
+    /// Print every reduction step instead of just the result (text or json)
+    #[structopt(
+        long,
+        value_name = "format",
+        require_equals = true,
+        conflicts_with_all = &["vm", "exact", "emit"]
+    )]
+    trace: Option<Option<TraceFormat>>,
+
+    /// Print the derivative of the program's result with respect to <var> instead of evaluating it
+    #[structopt(long, value_name = "var")]
+    derive: Option<String>,
 }
 
-fn parser() -> impl Parser<char, Expr, Error = Simple<char>> {
-    let number = text::int(10).map(|s: String| Expr::Num(s.parse().unwrap()));
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Rewrite files in canonical form
+    Fmt {
+        /// Only report files that are not formatted, without changing them
+        #[structopt(long)]
+        check: bool,
+
+        #[structopt(required = true)]
+        files: Vec<String>,
+    },
+
+    /// Type-check files without running them
+    Check {
+        #[structopt(required = true)]
+        files: Vec<String>,
+    },
 
-    let oper = |c| just(c).padded();
+    /// Print the source of a program exported with `--emit=json`
+    Import { file: String },
 
-    let negate_expr = oper('-')
-        .repeated()
-        .then(number)
-        .foldr(move |_, rhs| Expr::Negative(Box::new(rhs)));
+    /// Translate a program to a standalone C file
+    Compile {
+        file: String,
 
-    let deref_expr = oper('~')
-        .repeated()
-        .then(number)
-        .foldr(move |_, rhs| Expr::Invert(Box::new(rhs)));
+        /// Where to write the C source, standard output when omitted
+        #[structopt(short, long)]
+        output: Option<String>,
+    },
 
-    let unary_expr = deref_expr.or(negate_expr);
+    /// Run a language server over standard input and output
+    Lsp,
 
-    let product_expr = unary_expr
-        .then(
-            oper('*')
-                .to(Expr::Mult as fn(_, _) -> _)
-                .or(oper('/').to(Expr::Div as fn(_, _) -> _))
-                .then(unary_expr)
-                .repeated(),
-        )
-        .foldl(|lhs, (op, rhs)| op(Box::new(lhs), Box::new(rhs)));
+    /// Compare the bytecode VM with tree walking on a generated expression
+    Bench {
+        #[structopt(long, default_value = "12")]
+        depth: u32,
 
-    let sum_expr = product_expr
-        .then(
-            oper('+')
-                .to(Expr::Add as fn(_, _) -> _)
-                .or(oper('-').to(Expr::Sub as fn(_, _) -> _))
-                .then(product_expr)
-                .repeated(),
-        )
-        .foldl(|lhs, (op, rhs)| op(Box::new(lhs), Box::new(rhs)));
+        #[structopt(long, default_value = "10000")]
+        iterations: usize,
 
-    sum_expr.padded().then_ignore(end())
+        #[structopt(long, default_value = "42")]
+        seed: u64,
+    },
 }
 
 fn main() {
-    let src = std::fs::read_to_string(std::env::args().nth(1).unwrap()).unwrap();
+    let args = CliOptions::from_args();
+
+    let ok = match (&args.command, &args.file) {
+        (Some(Command::Fmt { check, files }), _) => fmt_files(files, *check),
+        (Some(Command::Check { files }), _) => check_files(files),
+        (Some(Command::Import { file }), _) => import_file(file),
+        (Some(Command::Lsp), _) => match lsp::run() {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                false
+            }
+        },
+        (Some(Command::Compile { file, output }), _) => compile_file(file, output.as_deref()),
+        (
+            Some(Command::Bench {
+                depth,
+                iterations,
+                seed,
+            }),
+            _,
+        ) => bench::run(*depth, *iterations, *seed),
+        (None, Some(filename)) => run_file(filename, &args),
+        (None, None) => match repl::run() {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                false
+            }
+        },
+    };
+
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+fn read_source(filename: &str) -> Option<String> {
+    match std::fs::read_to_string(filename) {
+        Ok(src) => Some(src),
+        Err(err) => {
+            eprintln!("error: could not read `{}`: {}", filename, err);
+            None
+        }
+    }
+}
+
+fn report_parse_errors(filename: &str, src: &str, errs: &Diagnostics) {
+    eprintln!("{}\n", errs.render(filename, src));
+    eprintln!(
+        "error: could not parse `{}` due to {} error(s)",
+        filename,
+        errs.0.len()
+    );
+}
+
+fn read_program(filename: &str) -> Option<(String, Program)> {
+    let src = read_source(filename)?;
+
+    match ast_tree::parse(&src) {
+        Ok(program) => Some((src, program)),
+        Err(errs) => {
+            report_parse_errors(filename, &src, &errs);
+            None
+        }
+    }
+}
+
+fn emit_tokens(filename: &str) -> bool {
+    let src = match read_source(filename) {
+        Some(src) => src,
+        None => return false,
+    };
+
+    match lexer::lex(&src) {
+        Ok(tokens) => {
+            print!("{}", lexer::format_tokens(&tokens));
+            true
+        }
+        Err(errs) => {
+            let errs = errs.iter().map(Diagnostic::from).collect();
+            report_parse_errors(filename, &src, &errs);
+            false
+        }
+    }
+}
+
+fn check_program(filename: &str, src: &str, program: &Program) -> Option<check::Types> {
+    match check::check_program(program) {
+        Ok(ty) => Some(ty),
+        Err(errs) => {
+            for err in &errs {
+                eprintln!("{}\n", diagnostics::render(filename, src, &err.into()));
+            }
+
+            eprintln!(
+                "error: could not type-check `{}` due to {} error(s)",
+                filename,
+                errs.len()
+            );
+            None
+        }
+    }
+}
+
+fn run_file(filename: &str, args: &CliOptions) -> bool {
+    if let Some(Emit::Tokens) = args.emit {
+        return emit_tokens(filename);
+    }
+
+    let (src, mut program) = match read_program(filename) {
+        Some(program) => program,
+        None => return false,
+    };
+
+    // The variable to derive by is usually free, which the checker would reject.
+    if let Some(var) = &args.derive {
+        return derive_program(filename, &src, &program, var);
+    }
+
+    // Exporting the tree doesn't need it to be well typed, unless it gets optimized.
+    let tree_only = matches!(args.emit, Some(Emit::Dot | Emit::Json));
+
+    if (!tree_only || args.optimize) && check_program(filename, &src, &program).is_none() {
+        return false;
+    }
+
+    if args.optimize {
+        let (optimized, rewrites) = optimize::optimize(&program);
+
+        eprintln!("{} rewrite(s) fired", rewrites.len());
+
+        for rewrite in &rewrites {
+            eprintln!(
+                "  {}: {} => {}",
+                rewrite.rule, rewrite.before, rewrite.after
+            );
+        }
+
+        eprint!("\n{}\n", format::format_program(&optimized));
+        program = optimized;
+    }
+
+    match args.emit {
+        Some(Emit::Dot) => {
+            print!("{}", dot::to_dot(&program));
+            return true;
+        }
+        Some(Emit::Json) => {
+            println!("{:#}", json::to_json(&program));
+            return true;
+        }
+        _ => {}
+    }
+
+    if let Some(format) = args.trace {
+        return trace_program(
+            filename,
+            &src,
+            &program,
+            format.unwrap_or(TraceFormat::Text),
+        );
+    }
+
+    let result = if args.exact {
+        let style = exact::Style {
+            mixed: args.mixed,
+            digits: args.digits,
+        };
+
+        exact::eval_program(&program, &Env::new(), &mut |value| {
+            println!("{}", style.format(&value))
+        })
+        .map(|value| value.map(|value| style.format(&value)))
+        .map_err(|err| diagnostics::render(filename, &src, &(&err).into()))
+    } else if args.emit.is_some() || args.vm {
+        let chunk = match vm::compile(&program) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return false;
+            }
+        };
+
+        if let Some(Emit::Bytecode) = args.emit {
+            print!("{}", vm::disassemble(&chunk));
+            return true;
+        }
+
+        vm::Vm::new()
+            .run_with_env(&chunk, &Env::new(), &mut |value| println!("{}", value))
+            .map(|value| value.map(|value| value.to_string()))
+            .map_err(|err| format!("error: {}", err))
+    } else {
+        eval::eval_program(&program, &Env::new(), &mut |value| println!("{}", value))
+            .map(|value| value.map(|value| value.to_string()))
+            .map_err(|err| diagnostics::render(filename, &src, &(&err).into()))
+    };
+
+    match result {
+        Ok(Some(x)) => {
+            println!("{}", x);
+            true
+        }
+        Ok(None) => true,
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+fn derive_program(filename: &str, src: &str, program: &Program, var: &str) -> bool {
+    match derive::derive(program, var) {
+        Ok(derivative) => {
+            println!("{}", format::format_expr(&derivative));
+            true
+        }
+        Err(err) => {
+            eprintln!("{}", diagnostics::render(filename, src, &(&err).into()));
+            false
+        }
+    }
+}
+
+fn trace_program(filename: &str, src: &str, program: &Program, format: TraceFormat) -> bool {
+    let mut traces = Vec::new();
+    let result = trace::trace_program(program, &Env::new(), &mut |trace| match format {
+        TraceFormat::Text => print!("{}", trace::format_trace(&trace)),
+        TraceFormat::Json => traces.push(trace),
+    });
+
+    if let TraceFormat::Json = format {
+        println!("{:#}", trace::to_json(&traces, &result));
+    }
+
+    match result {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("{}", diagnostics::render(filename, src, &(&err).into()));
+            false
+        }
+    }
+}
+
+fn check_files(files: &[String]) -> bool {
+    let mut ok = true;
+
+    for filename in files {
+        let types = read_program(filename)
+            .and_then(|(src, program)| check_program(filename, &src, &program));
+
+        match types {
+            Some(types) => match types.result {
+                Some(ty) => println!("{}: {}", filename, ty),
+                None => println!("{}: ok", filename),
+            },
+            None => ok = false,
+        }
+    }
+
+    ok
+}
+
+fn import_file(filename: &str) -> bool {
+    let src = match read_source(filename) {
+        Some(src) => src,
+        None => return false,
+    };
+
+    match json::from_json(&src) {
+        Ok(program) => {
+            print!("{}", format::format_program(&program));
+            true
+        }
+        Err(err) => {
+            eprintln!("error: could not import `{}`: {}", filename, err);
+            false
+        }
+    }
+}
+
+fn compile_file(filename: &str, output: Option<&str>) -> bool {
+    let (src, program) = match read_program(filename) {
+        Some(program) => program,
+        None => return false,
+    };
+
+    let types = match check_program(filename, &src, &program) {
+        Some(types) => types,
+        None => return false,
+    };
+
+    let code = match c::to_c(&program, &types, filename) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return false;
+        }
+    };
+
+    match output {
+        Some(output) => match std::fs::write(output, code) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("error: could not write `{}`: {}", output, err);
+                false
+            }
+        },
+        None => {
+            print!("{}", code);
+            true
+        }
+    }
+}
+
+fn fmt_files(files: &[String], check: bool) -> bool {
+    let mut ok = true;
+
+    for filename in files {
+        let (src, program) = match read_program(filename) {
+            Some(program) => program,
+            None => {
+                ok = false;
+                continue;
+            }
+        };
+
+        let formatted = format::format_source(&program, &src);
+
+        if formatted == src {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", filename);
+            ok = false;
+        } else if let Err(err) = std::fs::write(filename, formatted) {
+            eprintln!("error: could not write `{}`: {}", filename, err);
+            ok = false;
+        }
+    }
 
-    println!("{:?}", parser().parse(src));
+    ok
 }
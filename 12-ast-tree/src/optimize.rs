@@ -0,0 +1,564 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::builtins;
+use crate::format::format_expr;
+use crate::units;
+
+// Rewrites assume values are finite and that operands have the types their operators
+// expect, so `!!x` is a boolean. Units aren't assumed away: `x + 0`, `x - 0` and
+// `x - x` are only rewritten when `x` is known to be a plain number, since with units
+// they fail or keep the unit. Anything that could turn a division by zero or an
+// undefined variable into a value, like `x / 0 - x / 0` or `~~x`, is left alone so
+// errors still surface at runtime.
+#[derive(Debug, PartialEq)]
+pub struct Rewrite {
+    pub rule: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+struct Optimizer {
+    // Names in scope, and whether each is known to hold a plain number.
+    bound: Vec<(String, bool)>,
+    functions: Vec<String>,
+    rewrites: Vec<Rewrite>,
+}
+
+pub fn optimize(program: &Program) -> (Program, Vec<Rewrite>) {
+    let mut optimizer = Optimizer {
+        bound: Vec::new(),
+        functions: Vec::new(),
+        rewrites: Vec::new(),
+    };
+
+    let stmts = program
+        .stmts
+        .iter()
+        .map(|stmt| match stmt {
+            Stmt::Let(name, value) => {
+                let value = optimizer.simplify(value.clone());
+                optimizer
+                    .bound
+                    .push((name.clone(), optimizer.plain(&value)));
+                Stmt::Let(name.clone(), value)
+            }
+            Stmt::Fn(name, params, body) => {
+                let outer = optimizer.bound.len();
+
+                optimizer.functions.push(name.clone());
+                optimizer
+                    .bound
+                    .extend(params.iter().map(|param| (param.clone(), false)));
+                let body = optimizer.simplify(body.clone());
+                optimizer.bound.truncate(outer);
+
+                Stmt::Fn(name.clone(), params.clone(), body)
+            }
+            Stmt::Print(value) => Stmt::Print(optimizer.simplify(value.clone())),
+        })
+        .collect();
+
+    let result = program
+        .result
+        .as_ref()
+        .map(|result| optimizer.simplify(result.clone()));
+
+    (Program { stmts, result }, optimizer.rewrites)
+}
+
+fn num(expr: &Expr) -> Option<f64> {
+    match expr.kind {
+        ExprKind::Num(x, _) => Some(x),
+        _ => None,
+    }
+}
+
+impl Optimizer {
+    fn simplify(&mut self, expr: Expr) -> Expr {
+        let Expr {
+            kind,
+            span,
+            position,
+        } = expr;
+
+        let kind = match kind {
+            ExprKind::Num(_, _)
+            | ExprKind::Quantity(_, _)
+            | ExprKind::Bool(_)
+            | ExprKind::Var(_) => kind,
+            ExprKind::Call(name, args) => ExprKind::Call(
+                name,
+                args.into_iter().map(|arg| self.simplify(arg)).collect(),
+            ),
+            ExprKind::Negative(x) => ExprKind::Negative(Box::new(self.simplify(*x))),
+            ExprKind::Invert(x) => ExprKind::Invert(Box::new(self.simplify(*x))),
+            ExprKind::Not(x) => ExprKind::Not(Box::new(self.simplify(*x))),
+
+            ExprKind::Add(lhs, rhs) => self.binary(ExprKind::Add, *lhs, *rhs),
+            ExprKind::Sub(lhs, rhs) => self.binary(ExprKind::Sub, *lhs, *rhs),
+            ExprKind::Mult(lhs, rhs) => self.binary(ExprKind::Mult, *lhs, *rhs),
+            ExprKind::Div(lhs, rhs) => self.binary(ExprKind::Div, *lhs, *rhs),
+            ExprKind::Mod(lhs, rhs) => self.binary(ExprKind::Mod, *lhs, *rhs),
+            ExprKind::Pow(lhs, rhs) => self.binary(ExprKind::Pow, *lhs, *rhs),
+            ExprKind::Eq(lhs, rhs) => self.binary(ExprKind::Eq, *lhs, *rhs),
+            ExprKind::NotEq(lhs, rhs) => self.binary(ExprKind::NotEq, *lhs, *rhs),
+            ExprKind::Less(lhs, rhs) => self.binary(ExprKind::Less, *lhs, *rhs),
+            ExprKind::LessEq(lhs, rhs) => self.binary(ExprKind::LessEq, *lhs, *rhs),
+            ExprKind::Greater(lhs, rhs) => self.binary(ExprKind::Greater, *lhs, *rhs),
+            ExprKind::GreaterEq(lhs, rhs) => self.binary(ExprKind::GreaterEq, *lhs, *rhs),
+            ExprKind::And(lhs, rhs) => self.binary(ExprKind::And, *lhs, *rhs),
+            ExprKind::Or(lhs, rhs) => self.binary(ExprKind::Or, *lhs, *rhs),
+
+            ExprKind::If(cond, then, otherwise) => ExprKind::If(
+                Box::new(self.simplify(*cond)),
+                Box::new(self.simplify(*then)),
+                Box::new(self.simplify(*otherwise)),
+            ),
+            ExprKind::Lambda(params, body) => {
+                let outer = self.bound.len();
+
+                self.bound
+                    .extend(params.iter().map(|param| (param.clone(), false)));
+                let body = self.simplify(*body);
+                self.bound.truncate(outer);
+
+                ExprKind::Lambda(params, Box::new(body))
+            }
+            ExprKind::List(items) => {
+                ExprKind::List(items.into_iter().map(|item| self.simplify(item)).collect())
+            }
+        };
+
+        let mut expr = Expr {
+            kind,
+            span,
+            position,
+        };
+
+        while let Some((rule, after)) = self.rewrite(&expr) {
+            self.rewrites.push(Rewrite {
+                rule,
+                before: format_expr(&expr),
+                after: format_expr(&after),
+            });
+
+            expr = after;
+        }
+
+        expr
+    }
+
+    fn binary(
+        &mut self,
+        op: fn(Box<Expr>, Box<Expr>) -> ExprKind,
+        lhs: Expr,
+        rhs: Expr,
+    ) -> ExprKind {
+        op(Box::new(self.simplify(lhs)), Box::new(self.simplify(rhs)))
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.bound.iter().any(|(bound, _)| bound == name)
+    }
+
+    // Whether `expr` is a number without a unit, if it evaluates at all.
+    fn plain(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Num(_, _) => true,
+            ExprKind::Var(name) => self
+                .bound
+                .iter()
+                .rev()
+                .find(|(bound, _)| bound == name)
+                .is_some_and(|(_, plain)| *plain),
+            ExprKind::Negative(x) | ExprKind::Invert(x) | ExprKind::Pow(x, _) => self.plain(x),
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mult(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::If(_, lhs, rhs) => self.plain(lhs) && self.plain(rhs),
+            _ => false,
+        }
+    }
+
+    fn can_fail(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Num(_, _) | ExprKind::Bool(_) | ExprKind::Lambda(_, _) => false,
+            ExprKind::List(items) => items.iter().any(|item| self.can_fail(item)),
+            ExprKind::Quantity(_, unit) => unit.iter().any(|(name, _)| !units::is_unit(name)),
+            ExprKind::Call(_, _) => true,
+            ExprKind::Var(name) => !self.is_bound(name),
+            ExprKind::Negative(x) | ExprKind::Not(x) => self.can_fail(x),
+            ExprKind::Invert(x) => num(x).is_none_or(|x| x == 0.0) || self.can_fail(x),
+
+            ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs) => {
+                num(rhs).is_none_or(|x| x == 0.0) || self.can_fail(lhs) || self.can_fail(rhs)
+            }
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mult(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::NotEq(lhs, rhs)
+            | ExprKind::Less(lhs, rhs)
+            | ExprKind::LessEq(lhs, rhs)
+            | ExprKind::Greater(lhs, rhs)
+            | ExprKind::GreaterEq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs) => self.can_fail(lhs) || self.can_fail(rhs),
+            ExprKind::If(cond, then, otherwise) => {
+                self.can_fail(cond) || self.can_fail(then) || self.can_fail(otherwise)
+            }
+        }
+    }
+
+    // A rewritten node keeps the span and position of the node it replaces.
+    fn rewrite(&self, expr: &Expr) -> Option<(&'static str, Expr)> {
+        use ExprKind::*;
+
+        let at = |kind| Expr {
+            kind,
+            span: expr.span.clone(),
+            position: expr.position,
+        };
+        let node = |kind| Box::new(at(kind));
+
+        let (rule, kind) = match &expr.kind {
+            Negative(x) => match &x.kind {
+                Num(x, _) => ("fold constants", ExprKind::num(-x)),
+                Negative(x) => ("double negative", x.kind.clone()),
+                _ => return None,
+            },
+            Invert(x) => match &x.kind {
+                Num(x, _) if *x != 0.0 => ("fold constants", ExprKind::num(1.0 / x)),
+                Invert(y) if !self.can_fail(x) => ("double invert", y.kind.clone()),
+                _ => return None,
+            },
+
+            Add(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) => ("fold constants", ExprKind::num(a + b)),
+                (_, Num(z, _)) if *z == 0.0 && self.plain(lhs) => ("x + 0 = x", lhs.kind.clone()),
+                (Num(z, _), _) if *z == 0.0 && self.plain(rhs) => ("x + 0 = x", rhs.kind.clone()),
+                (Add(x, a), Num(b, _)) if num(a).is_some() => (
+                    "combine constants",
+                    Add(x.clone(), node(ExprKind::num(num(a)? + b))),
+                ),
+                (Sub(x, a), Num(b, _)) if num(a).is_some() => (
+                    "combine constants",
+                    Add(x.clone(), node(ExprKind::num(b - num(a)?))),
+                ),
+                (_, Num(b, _)) if *b < 0.0 => {
+                    ("x + -a = x - a", Sub(lhs.clone(), node(ExprKind::num(-b))))
+                }
+                (_, Negative(y)) => ("x + -y = x - y", Sub(lhs.clone(), y.clone())),
+                _ => return None,
+            },
+            Sub(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) => ("fold constants", ExprKind::num(a - b)),
+                (_, Num(z, _)) if *z == 0.0 && self.plain(lhs) => ("x - 0 = x", lhs.kind.clone()),
+                _ if lhs == rhs && self.plain(lhs) && !self.can_fail(lhs) => {
+                    ("x - x = 0", ExprKind::num(0.0))
+                }
+                // `x - a` is treated as `x + -a` so constants on either side combine.
+                (Add(_, a) | Sub(_, a), Num(b, _)) if num(a).is_some() => {
+                    ("x - a = x + -a", Add(lhs.clone(), node(ExprKind::num(-b))))
+                }
+                (_, Num(b, _)) if *b < 0.0 => {
+                    ("x - -a = x + a", Add(lhs.clone(), node(ExprKind::num(-b))))
+                }
+                (_, Negative(y)) => ("x - -y = x + y", Add(lhs.clone(), y.clone())),
+                _ => return None,
+            },
+            Mult(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) => ("fold constants", ExprKind::num(a * b)),
+                (_, Num(o, _)) if *o == 1.0 => ("x * 1 = x", lhs.kind.clone()),
+                (Num(o, _), _) if *o == 1.0 => ("x * 1 = x", rhs.kind.clone()),
+                (Mult(x, a), Num(b, _)) if num(a).is_some() => (
+                    "combine constants",
+                    Mult(x.clone(), node(ExprKind::num(num(a)? * b))),
+                ),
+                _ => return None,
+            },
+            Div(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) if *b != 0.0 => ("fold constants", ExprKind::num(a / b)),
+                (_, Num(o, _)) if *o == 1.0 => ("x / 1 = x", lhs.kind.clone()),
+                _ => return None,
+            },
+            Mod(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) if *b != 0.0 => ("fold constants", ExprKind::num(a % b)),
+                _ => return None,
+            },
+            Pow(lhs, rhs) => match (&lhs.kind, &rhs.kind) {
+                (Num(a, _), Num(b, _)) => ("fold constants", ExprKind::num(a.powf(*b))),
+                (_, Num(o, _)) if *o == 1.0 => ("x ^ 1 = x", lhs.kind.clone()),
+                _ => return None,
+            },
+            // Only built-ins that no user definition shadows are folded.
+            Call(name, args) if !self.functions.contains(name) && !self.is_bound(name) => {
+                let (_, builtin) = builtins::builtin(name)?;
+                let args = args.iter().map(num).collect::<Option<Vec<_>>>()?;
+
+                if args.len() != builtin.arity {
+                    return None;
+                }
+
+                ("fold constants", ExprKind::num(builtin.apply(&args)))
+            }
+            Not(x) => match &x.kind {
+                Bool(b) => ("fold constants", Bool(!b)),
+                Not(x) => ("double not", x.kind.clone()),
+                _ => return None,
+            },
+
+            Eq(lhs, rhs) | NotEq(lhs, rhs) => {
+                let equal = match (&lhs.kind, &rhs.kind) {
+                    (Num(a, _), Num(b, _)) => a == b,
+                    (Bool(a), Bool(b)) => a == b,
+                    _ => return None,
+                };
+
+                (
+                    "fold constants",
+                    Bool(equal == matches!(expr.kind, Eq(_, _))),
+                )
+            }
+            Less(lhs, rhs) | LessEq(lhs, rhs) | Greater(lhs, rhs) | GreaterEq(lhs, rhs) => {
+                let (a, b) = (num(lhs)?, num(rhs)?);
+
+                let result = match expr.kind {
+                    Less(_, _) => a < b,
+                    LessEq(_, _) => a <= b,
+                    Greater(_, _) => a > b,
+                    _ => a >= b,
+                };
+
+                ("fold constants", Bool(result))
+            }
+            And(lhs, rhs) => match lhs.kind {
+                Bool(false) => ("false && x = false", Bool(false)),
+                Bool(true) => ("true && x = x", rhs.kind.clone()),
+                _ => return None,
+            },
+            Or(lhs, rhs) => match lhs.kind {
+                Bool(true) => ("true || x = true", Bool(true)),
+                Bool(false) => ("false || x = x", rhs.kind.clone()),
+                _ => return None,
+            },
+            If(cond, then, otherwise) => match &cond.kind {
+                Bool(true) => ("constant condition", then.kind.clone()),
+                Bool(false) => ("constant condition", otherwise.kind.clone()),
+                Not(cond) => (
+                    "if !c then a else b = if c then b else a",
+                    If(cond.clone(), otherwise.clone(), then.clone()),
+                ),
+                _ => return None,
+            },
+
+            Num(_, _) | Quantity(_, _) | Bool(_) | Var(_) | Call(_, _) | Lambda(_, _) | List(_) => {
+                return None
+            }
+        };
+
+        // `inf` and `NaN` can't be written as literals, so they're left to the evaluator.
+        if matches!(kind, Num(x, _) if !x.is_finite()) {
+            return None;
+        }
+
+        Some((rule, at(kind)))
+    }
+}
+
+#[cfg(test)]
+fn optimize_str(src: &str) -> (String, Vec<&'static str>) {
+    let (program, rewrites) = optimize(&crate::parser::parse(src).unwrap());
+
+    (
+        crate::format::format_program(&program)
+            .trim_end()
+            .to_string(),
+        rewrites.iter().map(|r| r.rule).collect(),
+    )
+}
+
+#[test]
+fn test_optimize_folds_constants() {
+    assert_eq!(
+        optimize_str("1 + 2 * 3 - ~4"),
+        (
+            "6.75".to_string(),
+            vec![
+                "fold constants",
+                "fold constants",
+                "fold constants",
+                "fold constants"
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_optimize_keeps_division_by_zero() {
+    assert_eq!(optimize_str("1 / 0").0, "1 / 0");
+    assert_eq!(optimize_str("~(2 - 2)").0, "~0");
+    assert_eq!(optimize_str("let y = 0; ~~y").0, "let y = 0;\n~~y");
+}
+
+#[test]
+fn test_optimize_double_negative_and_invert() {
+    assert_eq!(optimize_str("--x").0, "x");
+    assert_eq!(optimize_str("~~x").0, "~~x");
+    assert_eq!(optimize_str("~~(x + 1 / 0)").0, "~~(x + 1 / 0)");
+    assert_eq!(optimize_str("-~-~x").0, "-~-~x");
+}
+
+#[test]
+fn test_optimize_identities() {
+    assert_eq!(optimize_str("let x = 2; x * 1 + 0").0, "let x = 2;\nx");
+    assert_eq!(optimize_str("let x = 2; 1 * x / 1 - 0").0, "let x = 2;\nx");
+    assert_eq!(optimize_str("let x = 2; 0 + x").0, "let x = 2;\nx");
+    // `0 - x` is `0` but `-x` is `-0` when `x` is zero.
+    assert_eq!(optimize_str("let x = 0; 0 - x").0, "let x = 0;\n0 - x");
+    assert_eq!(optimize_str("x * 1 + 0").0, "x + 0");
+}
+
+#[test]
+fn test_optimize_keeps_unit_errors() {
+    use crate::eval::{eval_program, Env};
+
+    for src in ["1 m + 0", "(1 m - 1 m) + 2", "let d = 1 m; d - 0"] {
+        let (optimized, rewrites) = optimize(&crate::parser::parse(src).unwrap());
+
+        assert_eq!(rewrites, vec![]);
+        assert!(eval_program(&optimized, &Env::new(), &mut |_| {}).is_err());
+    }
+}
+
+#[test]
+fn test_optimize_sub_self() {
+    assert_eq!(optimize_str("let x = 3; x - x").0, "let x = 3;\n0");
+    assert_eq!(
+        optimize_str("let y = 3; (y * 2) - (y * 2)").0,
+        "let y = 3;\n0"
+    );
+    assert_eq!(optimize_str("x - x").0, "x - x");
+    assert_eq!(
+        optimize_str("let y = 0; y / y - y / y").0,
+        "let y = 0;\ny / y - y / y"
+    );
+}
+
+#[test]
+fn test_optimize_combines_constants_through_sub() {
+    assert_eq!(
+        optimize_str("x + 2 - 3"),
+        (
+            "x - 1".to_string(),
+            vec!["x - a = x + -a", "combine constants", "x + -a = x - a"]
+        )
+    );
+    assert_eq!(optimize_str("x - 2 + 5").0, "x + 3");
+    assert_eq!(optimize_str("x * 2 * 3").0, "x * 6");
+}
+
+#[test]
+fn test_optimize_sub_of_negative() {
+    assert_eq!(optimize_str("a - -b").0, "a + b");
+    assert_eq!(optimize_str("a + -b").0, "a - b");
+    // Swapping the operands would change which error is reported first.
+    assert_eq!(optimize_str("-a + b").0, "-a + b");
+}
+
+#[test]
+fn test_optimize_reports_rewrites() {
+    let (_, rewrites) = optimize(&crate::parser::parse("let k = 2 * 3; --k").unwrap());
+
+    assert_eq!(
+        rewrites,
+        vec![
+            Rewrite {
+                rule: "fold constants",
+                before: "2 * 3".to_string(),
+                after: "6".to_string(),
+            },
+            Rewrite {
+                rule: "double negative",
+                before: "--k".to_string(),
+                after: "k".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_optimize_preserves_results() {
+    use crate::eval::{eval_program, Env, Value};
+
+    let mut seed = 7;
+    let env = Env::new()
+        .bind("x", Value::Num(1.5))
+        .bind("y", Value::Num(-2.0))
+        .bind("rate", Value::Num(0.25));
+
+    for _ in 0..500 {
+        let program = Program {
+            stmts: vec![],
+            result: Some(crate::bench::generate(&mut seed, 5)),
+        };
+        let (optimized, _) = optimize(&program);
+
+        if let Ok(Some(Value::Num(expected))) = eval_program(&program, &env, &mut |_| {}) {
+            let actual = eval_program(&optimized, &env, &mut |_| {})
+                .unwrap()
+                .unwrap()
+                .num()
+                .unwrap();
+
+            assert!(
+                (expected - actual).abs() <= 1e-9 * expected.abs().max(1.0),
+                "{} = {} but {} = {}",
+                crate::format::format_program(&program),
+                expected,
+                crate::format::format_program(&optimized),
+                actual
+            );
+        }
+    }
+}
+
+#[test]
+fn test_optimize_function_bodies_and_arguments() {
+    assert_eq!(
+        optimize_str("fn f(x) = x - x + 2 * 3; f(1 * y) - f(1 * y)").0,
+        "fn f(x) = x - x + 6;\nf(y) - f(y)"
+    );
+}
+
+#[test]
+fn test_optimize_power_modulo_and_builtins() {
+    assert_eq!(optimize_str("2 ^ 10 % 1000 + x ^ 1").0, "24 + x");
+    assert_eq!(optimize_str("sqrt(16) * max(x, 2)").0, "4 * max(x, 2)");
+    assert_eq!(optimize_str("1 % 0 + abs(1, 2)").0, "1 % 0 + abs(1, 2)");
+    assert_eq!(optimize_str("1e308 * 10").0, "1e308 * 10");
+    assert_eq!(optimize_str("sqrt(-1) + 2 ^ 2").0, "sqrt(-1) + 4");
+    assert_eq!(
+        optimize_str("fn sqrt(x) = x; sqrt(16)").0,
+        "fn sqrt(x) = x;\nsqrt(16)"
+    );
+}
+
+#[test]
+fn test_optimize_booleans_and_conditions() {
+    assert_eq!(optimize_str("1 < 2 && !(2 == 3)").0, "true");
+    assert_eq!(optimize_str("false && x").0, "false");
+    assert_eq!(optimize_str("true || 1 / 0 > 0").0, "true");
+    assert_eq!(optimize_str("false || x > 1").0, "x > 1");
+    assert_eq!(optimize_str("if 2 > 1 then x else y").0, "x");
+    assert_eq!(
+        optimize_str("if !(x > 1) then 1 else 2").0,
+        "if x > 1 then 2 else 1"
+    );
+    assert_eq!(
+        optimize_str("!!(x < y) && true != false").0,
+        "x < y && true"
+    );
+}
@@ -0,0 +1,1094 @@
+use crate::ast::{Digits, Expr, ExprKind, Position, Program, Span, Stmt, Unit};
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{self, Token};
+use crate::units;
+use chumsky::prelude::*;
+use chumsky::Stream;
+use std::collections::BTreeSet;
+
+const KEYWORDS: &[&str] = &["let", "fn", "print", "if", "then", "else", "true", "false"];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pattern<T> {
+    Token(T),
+    Label(&'static str),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind<T> {
+    Unexpected,
+    Unclosed { span: Span, delimiter: Box<T> },
+    Custom(String),
+}
+
+// The lexer reports errors about chars and the parser about tokens. Both are turned
+// into errors about their descriptions, like "`*`" or "name `x`", before they are
+// shown, so callers only deal with `ParseError<String>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError<T = String> {
+    pub span: Span,
+    pub kind: ErrorKind<T>,
+    pub expected: BTreeSet<Pattern<T>>,
+    pub found: Option<T>,
+    labelled: bool,
+}
+
+impl<T: Ord> ParseError<T> {
+    pub fn custom(span: Span, msg: String) -> Self {
+        ParseError {
+            span,
+            kind: ErrorKind::Custom(msg),
+            expected: BTreeSet::new(),
+            found: None,
+            labelled: false,
+        }
+    }
+
+    fn map<U: Ord>(self, f: impl Fn(T) -> U) -> ParseError<U> {
+        ParseError {
+            span: self.span,
+            kind: match self.kind {
+                ErrorKind::Unexpected => ErrorKind::Unexpected,
+                ErrorKind::Unclosed { span, delimiter } => ErrorKind::Unclosed {
+                    span,
+                    delimiter: Box::new(f(*delimiter)),
+                },
+                ErrorKind::Custom(msg) => ErrorKind::Custom(msg),
+            },
+            expected: self
+                .expected
+                .into_iter()
+                .map(|pattern| match pattern {
+                    Pattern::Token(token) => Pattern::Token(f(token)),
+                    Pattern::Label(label) => Pattern::Label(label),
+                    Pattern::End => Pattern::End,
+                })
+                .collect(),
+            found: self.found.map(&f),
+            labelled: self.labelled,
+        }
+    }
+}
+
+impl ParseError<char> {
+    pub fn describe(self) -> ParseError {
+        self.map(|c| format!("`{}`", c))
+    }
+}
+
+impl ParseError<Token> {
+    pub fn describe(self) -> ParseError {
+        self.map(|token| token.to_string())
+    }
+}
+
+impl ParseError {
+    pub fn message(&self) -> String {
+        let found = format!("unexpected {}", describe(self.found.as_ref()));
+
+        let expected = self
+            .expected
+            .iter()
+            .map(|pattern| match pattern {
+                Pattern::Token(token) => describe(Some(token)),
+                Pattern::Label(label) if KEYWORDS.contains(label) => format!("`{}`", label),
+                Pattern::Label(label) => label.to_string(),
+                Pattern::End => describe(None),
+            })
+            .collect::<Vec<_>>();
+
+        let found_and_expected = match expected.split_last() {
+            None => found,
+            Some((last, [])) => format!("{}, expected {}", found, last),
+            Some((last, rest)) => format!("{}, expected {} or {}", found, rest.join(", "), last),
+        };
+
+        match &self.kind {
+            ErrorKind::Unexpected => found_and_expected,
+            ErrorKind::Unclosed { delimiter, .. } => {
+                format!("unclosed delimiter {}", describe(Some(delimiter)))
+            }
+            ErrorKind::Custom(msg) => msg.clone(),
+        }
+    }
+}
+
+fn describe(token: Option<&String>) -> String {
+    match token {
+        Some(token) => token.clone(),
+        None => "end of input".to_string(),
+    }
+}
+
+impl<T: Ord> chumsky::Error<T> for ParseError<T> {
+    type Span = Span;
+    type Label = &'static str;
+
+    fn expected_input_found<Iter: IntoIterator<Item = Option<T>>>(
+        span: Span,
+        expected: Iter,
+        found: Option<T>,
+    ) -> Self {
+        ParseError {
+            span,
+            kind: ErrorKind::Unexpected,
+            expected: expected
+                .into_iter()
+                .map(|token| token.map_or(Pattern::End, Pattern::Token))
+                .collect(),
+            found,
+            labelled: false,
+        }
+    }
+
+    fn unclosed_delimiter(
+        unclosed_span: Span,
+        delimiter: T,
+        span: Span,
+        expected: T,
+        found: Option<T>,
+    ) -> Self {
+        ParseError {
+            span,
+            kind: ErrorKind::Unclosed {
+                span: unclosed_span,
+                delimiter: Box::new(delimiter),
+            },
+            expected: BTreeSet::from([Pattern::Token(expected)]),
+            found,
+            labelled: false,
+        }
+    }
+
+    // A labelled parser is reported by its name rather than by the tokens it was
+    // looking for, so `1 + *` expects "a number" instead of a list of digits.
+    fn with_label(mut self, label: &'static str) -> Self {
+        if !self.labelled {
+            self.labelled = true;
+            self.expected = BTreeSet::from([Pattern::Label(label)]);
+        }
+
+        self
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        if let (ErrorKind::Unexpected, ErrorKind::Unclosed { .. } | ErrorKind::Custom(_)) =
+            (&self.kind, &other.kind)
+        {
+            self.kind = other.kind;
+        }
+
+        self.expected.extend(other.expected);
+        self
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        let span = match &err.kind {
+            ErrorKind::Unclosed { span, .. } => span.clone(),
+            _ => err.span.clone(),
+        };
+
+        Diagnostic {
+            message: err.message(),
+            span,
+        }
+    }
+}
+
+// The unit after a number, like the `m/s^2` of `9.81 m/s^2`. Only known unit names
+// are taken, so the `x` in `2 m * x` is still a variable. A `*` or `/` only continues
+// the unit when a unit name follows it directly: `10 m/s` is a speed but `10 m / s`
+// divides by `s`, so a binding named like a unit is never ignored. A space before
+// the operator and none after it, like `10 m /s`, is an error.
+fn unit() -> impl Parser<Token, Unit, Error = ParseError<Token>> + Clone {
+    let name = select! { Token::Ident(name) if units::is_unit(&name) => name };
+    let power = op("^")
+        .ignore_then(op("-").or_not())
+        .then(select! { Token::Num(text) => text })
+        .validate(|(minus, text), span, emit| {
+            let power = parse_number(&text).map(|x| if minus.is_some() { -x } else { x });
+            let message = match power {
+                Ok(x) if x.fract() == 0.0 && x.abs() <= i32::MAX as f64 => {
+                    return x as i32;
+                }
+                Ok(x) if x.fract() == 0.0 => {
+                    format!("the power of a unit is too large: `{}`", text)
+                }
+                _ => format!("the power of a unit must be a whole number, not `{}`", text),
+            };
+
+            emit(ParseError::custom(span, message));
+            1
+        });
+    let part = name
+        .then(power.or_not().map(|power| power.unwrap_or(1)))
+        .map_with_span(|part, span: Span| (part, span));
+    let glued = op("*")
+        .map(|span| ("*", span))
+        .or(op("/").map(|span| ("/", span)))
+        .then(part.clone())
+        .try_map(|((op, op_span), (part, span)), _| {
+            if op_span.end == span.start {
+                Ok((op, op_span, part, span))
+            } else {
+                Err(ParseError::custom(
+                    op_span,
+                    "spaces after an operator end the unit".to_string(),
+                ))
+            }
+        });
+
+    part.then(glued.repeated())
+        .validate(|(first, rest), _, emit| {
+            let (mut unit, mut end) = (vec![first.0], first.1.end);
+
+            for (op, op_span, (name, power), span) in rest {
+                if op_span.start != end {
+                    emit(ParseError::custom(
+                        end..span.end,
+                        format!("a unit can't have a space before `{}`", op),
+                    ));
+                }
+
+                let sign = if op == "/" { -1 } else { 1 };
+                unit.push((name, sign * power));
+                end = span.end;
+            }
+
+            unit
+        })
+}
+
+fn number() -> impl Parser<Token, Expr, Error = ParseError<Token>> + Clone {
+    select! { Token::Num(text) => text }
+        .validate(|text, span, emit| match parse_number(&text) {
+            Ok(x) => (x, Some(text)),
+            Err(msg) => {
+                emit(ParseError::custom(span, msg));
+                (f64::NAN, None)
+            }
+        })
+        .then(unit().or_not())
+        .map_with_span(|((x, text), unit), span| match unit {
+            Some(unit) => Expr::new(ExprKind::Quantity(x, unit), span),
+            None => Expr::new(ExprKind::Num(x, Digits(text)), span),
+        })
+        .labelled("number")
+}
+
+pub fn parse_number(text: &str) -> Result<f64, String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let hex = text.starts_with("0x") || text.starts_with("0X");
+
+    // The `x` of `0x` and the `e` of an exponent aren't digits, so `0x_1` and `1_e5`
+    // are rejected.
+    for (i, c) in chars.iter().enumerate() {
+        let is_digit_at = |i: Option<usize>| {
+            i.and_then(|i| chars.get(i)).is_some_and(|c| {
+                if hex {
+                    c.is_ascii_hexdigit()
+                } else {
+                    c.is_ascii_digit()
+                }
+            })
+        };
+
+        if *c == '_' && !(is_digit_at(i.checked_sub(1)) && is_digit_at(Some(i + 1))) {
+            return Err(format!("`_` must be between digits in `{}`", text));
+        }
+    }
+
+    let cleaned = text.replace('_', "");
+
+    if let Some(digits) = cleaned
+        .strip_prefix("0x")
+        .or_else(|| cleaned.strip_prefix("0X"))
+    {
+        if digits.is_empty() {
+            return Err(format!("missing digits in hexadecimal literal `{}`", text));
+        }
+
+        if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "invalid digit `{}` in hexadecimal literal `{}`",
+                c, text
+            ));
+        }
+
+        return match u64::from_str_radix(digits, 16) {
+            Ok(x) => Ok(x as f64),
+            Err(_) => Err(format!("hexadecimal literal `{}` is too large", text)),
+        };
+    }
+
+    let (mantissa, exponent) = match cleaned.find(['e', 'E']) {
+        Some(i) => (&cleaned[..i], Some(&cleaned[i + 1..])),
+        None => (cleaned.as_str(), None),
+    };
+
+    if mantissa.ends_with('.') {
+        return Err(format!("missing digits after decimal point in `{}`", text));
+    }
+
+    if let Some(exponent) = exponent {
+        if !exponent
+            .trim_start_matches(['+', '-'])
+            .starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Err(format!("missing digits in exponent of `{}`", text));
+        }
+    }
+
+    match cleaned.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        Ok(_) => Err(format!("numeric literal `{}` is out of range", text)),
+        Err(_) => Err(format!("invalid numeric literal `{}`", text)),
+    }
+}
+
+fn ident() -> impl Parser<Token, String, Error = ParseError<Token>> + Clone {
+    name().map(|(name, _)| name)
+}
+
+// Keywords are their own tokens, so `let if = 1` fails here without a special case.
+fn name() -> impl Parser<Token, (String, Span), Error = ParseError<Token>> + Clone {
+    select! { Token::Ident(name) => name }
+        .map_with_span(|name, span| (name, span))
+        .labelled("name")
+}
+
+type TokenStream = Stream<'static, Token, Span, std::vec::IntoIter<(Token, Span)>>;
+
+// The end of input is at the end of `src`, after any trailing whitespace.
+fn stream(src: &str) -> Result<TokenStream, Vec<ParseError>> {
+    let len = src.len();
+
+    Ok(Stream::from_iter(len..len, lexer::lex(src)?.into_iter()))
+}
+
+fn describe_all(errs: Vec<ParseError<Token>>) -> Vec<ParseError> {
+    errs.into_iter()
+        .map(ParseError::<Token>::describe)
+        .collect()
+}
+
+pub fn parse(src: &str) -> Result<Program, Vec<ParseError>> {
+    let mut program = parser().parse(stream(src)?).map_err(describe_all)?;
+    let lines = Lines::new(src);
+
+    for stmt in &mut program.stmts {
+        lines.locate_stmt(stmt);
+    }
+
+    if let Some(result) = &mut program.result {
+        lines.locate(result);
+    }
+
+    Ok(program)
+}
+
+// Like `parse`, for a REPL line that may only define names.
+pub fn parse_repl(src: &str) -> Result<(Vec<Stmt>, Option<Expr>), Vec<ParseError>> {
+    parse(src).map(|program| (program.stmts, program.result))
+}
+
+// Parsers only see byte spans, so line and column are filled in once the whole tree
+// has been built, using the offsets where each line starts.
+struct Lines<'a> {
+    src: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(src: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Lines { src, starts }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.starts[line - 1];
+
+        Position {
+            line,
+            column: self.src[start..offset].chars().count() + 1,
+        }
+    }
+
+    fn locate(&self, expr: &mut Expr) {
+        expr.position = self.position(expr.span.start);
+
+        for child in expr.kind.children_mut() {
+            self.locate(child);
+        }
+    }
+
+    fn locate_stmt(&self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Let(_, expr) | Stmt::Fn(_, _, expr) | Stmt::Print(expr) => self.locate(expr),
+        }
+    }
+}
+
+pub fn parser() -> impl Parser<Token, Program, Error = ParseError<Token>> {
+    statements().map(|(stmts, result)| Program { stmts, result })
+}
+
+fn keyword(
+    token: Token,
+    name: &'static str,
+) -> impl Parser<Token, Span, Error = ParseError<Token>> + Clone {
+    just(token).map_with_span(|_, span| span).labelled(name)
+}
+
+fn op(op: &'static str) -> impl Parser<Token, Span, Error = ParseError<Token>> + Clone {
+    just(Token::Op(op)).map_with_span(|_, span| span)
+}
+
+type BinaryOp = fn(Box<Expr>, Box<Expr>) -> ExprKind;
+
+// A binary node spans from the start of its left operand to the end of its right one.
+fn binary(lhs: Expr, (op, rhs): (BinaryOp, Expr)) -> Expr {
+    let span = lhs.span.start..rhs.span.end;
+
+    Expr::new(op(Box::new(lhs), Box::new(rhs)), span)
+}
+
+fn unique_params(
+    params: Vec<String>,
+    span: Span,
+    emit: &mut dyn FnMut(ParseError<Token>),
+) -> Vec<String> {
+    for (i, param) in params.iter().enumerate() {
+        if params[..i].contains(param) {
+            emit(ParseError::custom(
+                span.clone(),
+                format!("duplicate parameter `{}`", param),
+            ));
+        }
+    }
+
+    params
+}
+
+fn statements() -> impl Parser<Token, (Vec<Stmt>, Option<Expr>), Error = ParseError<Token>> {
+    let expr = recursive(|expr| {
+        let args = op("(")
+            .ignore_then(expr.clone().separated_by(op(",")))
+            .then(op(")"));
+
+        let call_or_var = name()
+            .then(args.or_not())
+            .map(|((name, span), args)| match args {
+                Some((args, close)) => Expr::new(ExprKind::Call(name, args), span.start..close.end),
+                None => Expr::new(ExprKind::Var(name), span),
+            });
+
+        let boolean = just(Token::True)
+            .to(ExprKind::Bool(true))
+            .or(just(Token::False).to(ExprKind::Bool(false)))
+            .map_with_span(Expr::new)
+            .labelled("boolean");
+
+        let if_expr = keyword(Token::If, "if")
+            .then(expr.clone())
+            .then_ignore(keyword(Token::Then, "then"))
+            .then(expr.clone())
+            .then_ignore(keyword(Token::Else, "else"))
+            .then(expr.clone())
+            .map(|(((start, cond), then), otherwise)| {
+                let span = start.start..otherwise.span.end;
+                let kind = ExprKind::If(Box::new(cond), Box::new(then), Box::new(otherwise));
+
+                Expr::new(kind, span)
+            });
+
+        // Like the `else` branch of an `if`, the body of a lambda extends as far as it
+        // can, so `|x| x + 1` adds inside the lambda. `||` is a lambda without
+        // parameters.
+        let lambda = op("|")
+            .ignore_then(ident().separated_by(op(",")))
+            .then_ignore(op("|"))
+            .or(op("||").to(Vec::new()))
+            .validate(unique_params)
+            .map_with_span(|params, span: Span| (params, span))
+            .then(expr.clone())
+            .map(|((params, start), body): ((_, Span), Expr)| {
+                let span = start.start..body.span.end;
+                Expr::new(ExprKind::Lambda(params, Box::new(body)), span)
+            })
+            .labelled("lambda");
+
+        let list = op("[")
+            .then(expr.clone().separated_by(op(",")).allow_trailing())
+            .then(op("]"))
+            .map(|((open, items), close)| Expr::new(ExprKind::List(items), open.start..close.end))
+            .recover_with(nested_delimiters(
+                Token::Op("["),
+                Token::Op("]"),
+                [(Token::Op("("), Token::Op(")"))],
+                |span| Expr::new(ExprKind::num(f64::NAN), span),
+            ))
+            .labelled("list");
+
+        // A parenthesized expression spans its parentheses, so errors underline them too.
+        let parenthesized = op("(")
+            .then(expr.clone())
+            .then(op(")"))
+            .map(|((open, expr), close)| Expr {
+                span: open.start..close.end,
+                ..expr
+            })
+            .recover_with(nested_delimiters(
+                Token::Op("("),
+                Token::Op(")"),
+                [(Token::Op("["), Token::Op("]"))],
+                |span| Expr::new(ExprKind::num(f64::NAN), span),
+            ));
+
+        let atom = number()
+            .or(boolean)
+            .or(if_expr)
+            .or(call_or_var)
+            .or(lambda)
+            .or(list)
+            .or(parenthesized);
+
+        // `^` binds tighter than unary operators on its left but accepts them on its
+        // right, so `-2 ^ 2` is `-(2 ^ 2)` and `2 ^ -1` is allowed.
+        let unary_expr = recursive(|unary_expr| {
+            let power_expr = atom
+                .then(
+                    op("^")
+                        .to(ExprKind::Pow as fn(_, _) -> _)
+                        .then(unary_expr)
+                        .or_not(),
+                )
+                .map(|(lhs, rhs)| match rhs {
+                    Some(rhs) => binary(lhs, rhs),
+                    None => lhs,
+                });
+
+            op("-")
+                .map(|span| (ExprKind::Negative as fn(_) -> _, span))
+                .or(op("~").map(|span| (ExprKind::Invert as fn(_) -> _, span)))
+                .or(op("!").map(|span| (ExprKind::Not as fn(_) -> _, span)))
+                .repeated()
+                .then(power_expr)
+                .foldr(|(op, span), rhs| {
+                    let span = span.start..rhs.span.end;
+                    Expr::new(op(Box::new(rhs)), span)
+                })
+        });
+
+        let product_expr = unary_expr
+            .clone()
+            .then(
+                op("*")
+                    .to(ExprKind::Mult as fn(_, _) -> _)
+                    .or(op("/").to(ExprKind::Div as fn(_, _) -> _))
+                    .or(op("%").to(ExprKind::Mod as fn(_, _) -> _))
+                    .then(unary_expr)
+                    .repeated(),
+            )
+            .foldl(binary);
+
+        let sum_expr = product_expr
+            .clone()
+            .then(
+                op("+")
+                    .to(ExprKind::Add as fn(_, _) -> _)
+                    .or(op("-").to(ExprKind::Sub as fn(_, _) -> _))
+                    .then(product_expr)
+                    .repeated(),
+            )
+            .foldl(binary);
+
+        // Comparisons don't chain, `a < b < c` is a syntax error.
+        let compare_expr = sum_expr
+            .clone()
+            .then(
+                op("==")
+                    .to(ExprKind::Eq as fn(_, _) -> _)
+                    .or(op("!=").to(ExprKind::NotEq as fn(_, _) -> _))
+                    .or(op("<=").to(ExprKind::LessEq as fn(_, _) -> _))
+                    .or(op(">=").to(ExprKind::GreaterEq as fn(_, _) -> _))
+                    .or(op("<").to(ExprKind::Less as fn(_, _) -> _))
+                    .or(op(">").to(ExprKind::Greater as fn(_, _) -> _))
+                    .then(sum_expr)
+                    .or_not(),
+            )
+            .map(|(lhs, rhs)| match rhs {
+                Some(rhs) => binary(lhs, rhs),
+                None => lhs,
+            });
+
+        let and_expr = compare_expr
+            .clone()
+            .then(
+                op("&&")
+                    .to(ExprKind::And as fn(_, _) -> _)
+                    .then(compare_expr)
+                    .repeated(),
+            )
+            .foldl(binary);
+
+        and_expr
+            .clone()
+            .then(
+                op("||")
+                    .to(ExprKind::Or as fn(_, _) -> _)
+                    .then(and_expr)
+                    .repeated(),
+            )
+            .foldl(binary)
+    });
+
+    let let_stmt = keyword(Token::Let, "let").ignore_then(
+        ident()
+            .then_ignore(op("="))
+            .then(expr.clone())
+            .then_ignore(op(";"))
+            .map(|(name, value)| Stmt::Let(name, value))
+            .recover_with(
+                skip_until([Token::Op(";")], |span| {
+                    Stmt::Let(String::new(), Expr::new(ExprKind::num(f64::NAN), span))
+                })
+                .consume_end(),
+            ),
+    );
+
+    let params = ident()
+        .separated_by(op(","))
+        .delimited_by(op("("), op(")"))
+        .validate(unique_params);
+
+    let fn_stmt = keyword(Token::Fn, "fn").ignore_then(
+        ident()
+            .then(params)
+            .then_ignore(op("="))
+            .then(expr.clone())
+            .then_ignore(op(";"))
+            .map(|((name, params), body)| Stmt::Fn(name, params, body))
+            .recover_with(
+                skip_until([Token::Op(";")], |span| {
+                    Stmt::Let(String::new(), Expr::new(ExprKind::num(f64::NAN), span))
+                })
+                .consume_end(),
+            ),
+    );
+
+    let print_stmt = keyword(Token::Print, "print").ignore_then(
+        expr.clone()
+            .then_ignore(op(";"))
+            .map(Stmt::Print)
+            .recover_with(
+                skip_until([Token::Op(";")], |span| {
+                    Stmt::Print(Expr::new(ExprKind::num(f64::NAN), span))
+                })
+                .consume_end(),
+            ),
+    );
+
+    let_stmt
+        .or(fn_stmt)
+        .or(print_stmt)
+        .repeated()
+        .then(expr.or_not())
+        .then_ignore(end())
+}
+
+#[test]
+fn test_parse_unbalanced_parentheses_is_error() {
+    assert!(parse("(1 + 2").is_err());
+    assert!(parse("1 + 2)").is_err());
+    assert!(parse("()").is_err());
+}
+
+#[test]
+fn test_parse_number_errors() {
+    assert_eq!(
+        parse_number("0x"),
+        Err("missing digits in hexadecimal literal `0x`".to_string())
+    );
+    assert_eq!(
+        parse_number("0xfg"),
+        Err("invalid digit `g` in hexadecimal literal `0xfg`".to_string())
+    );
+    assert_eq!(
+        parse_number("0x1_0000_0000_0000_0000"),
+        Err("hexadecimal literal `0x1_0000_0000_0000_0000` is too large".to_string())
+    );
+    assert_eq!(
+        parse_number("1_"),
+        Err("`_` must be between digits in `1_`".to_string())
+    );
+    assert_eq!(
+        parse_number("1__0"),
+        Err("`_` must be between digits in `1__0`".to_string())
+    );
+    assert_eq!(
+        parse_number("1._5"),
+        Err("`_` must be between digits in `1._5`".to_string())
+    );
+    for text in ["0x_1F", "1_e5", "1e_5", "1_.5"] {
+        assert_eq!(
+            parse_number(text),
+            Err(format!("`_` must be between digits in `{}`", text))
+        );
+    }
+    assert_eq!(parse_number("0x1_f"), Ok(31.0));
+    assert_eq!(
+        parse_number("1."),
+        Err("missing digits after decimal point in `1.`".to_string())
+    );
+    assert_eq!(
+        parse_number("1e"),
+        Err("missing digits in exponent of `1e`".to_string())
+    );
+    assert_eq!(
+        parse_number("1e+"),
+        Err("missing digits in exponent of `1e+`".to_string())
+    );
+    assert_eq!(
+        parse_number("1e400"),
+        Err("numeric literal `1e400` is out of range".to_string())
+    );
+}
+
+#[test]
+fn test_parse_malformed_literal_is_error() {
+    let errs = parse("1 + 0xg").unwrap_err();
+
+    assert_eq!(errs.len(), 1);
+    assert_eq!(
+        errs[0].kind,
+        ErrorKind::Custom("invalid digit `g` in hexadecimal literal `0xg`".to_string())
+    );
+    assert_eq!(errs[0].span, 4..7);
+}
+
+#[test]
+fn test_parse_let_statements() {
+    let program = parse("let rate = 3; let n = 12; rate * n").unwrap();
+
+    assert_eq!(
+        program.stmts,
+        vec![
+            Stmt::Let("rate".to_string(), ExprKind::num(3.0).into()),
+            Stmt::Let("n".to_string(), ExprKind::num(12.0).into()),
+        ]
+    );
+    assert_eq!(
+        program.result.unwrap(),
+        ExprKind::Mult(
+            Box::new(ExprKind::Var("rate".to_string()).into()),
+            Box::new(ExprKind::Var("n".to_string()).into())
+        )
+        .into()
+    );
+}
+
+#[test]
+fn test_parse_keyword_is_not_a_name() {
+    assert!(parse("let let = 1; let").is_err());
+    assert!(parse("let letter = 1; letter").is_ok());
+}
+
+#[test]
+fn test_parse_result_is_optional() {
+    assert_eq!(
+        parse("print 1; print 2;"),
+        Ok(Program {
+            stmts: vec![
+                Stmt::Print(ExprKind::num(1.0).into()),
+                Stmt::Print(ExprKind::num(2.0).into()),
+            ],
+            result: None,
+        })
+    );
+    assert_eq!(parse("let x = 1;").unwrap().result, None);
+    assert!(parse("let x = 1 x").is_err());
+}
+
+#[test]
+fn test_parse_repl_result_is_optional() {
+    assert_eq!(
+        parse_repl("let x = 1;"),
+        Ok((
+            vec![Stmt::Let("x".to_string(), ExprKind::num(1.0).into())],
+            None
+        ))
+    );
+    assert_eq!(
+        parse_repl("x"),
+        Ok((vec![], Some(ExprKind::Var("x".to_string()).into())))
+    );
+    assert_eq!(parse_repl(""), Ok((vec![], None)));
+}
+
+#[test]
+fn test_parse_spans_are_byte_offsets() {
+    let errs = parse("\"π\" + 1").unwrap_err();
+
+    assert_eq!(errs[0].span, 0..1);
+
+    let errs = parse("π + 0xg").unwrap_err();
+
+    assert_eq!(errs[0].span, 0..2);
+}
+
+#[test]
+fn test_parse_recovers_from_several_errors() {
+    let errs = parse("let a = 1 +; let b = (2 *); let c = 0x; a").unwrap_err();
+
+    assert_eq!(errs.len(), 3);
+    assert_eq!(errs[0].span, 11..12);
+    assert_eq!(errs[1].span, 25..26);
+    assert_eq!(errs[2].span, 36..38);
+}
+
+#[test]
+fn test_parse_functions() {
+    let program = parse("fn area(w, h) = w * h;\nfn one() = 1;\narea(one(), 2)").unwrap();
+
+    assert_eq!(
+        program.stmts,
+        vec![
+            Stmt::Fn(
+                "area".to_string(),
+                vec!["w".to_string(), "h".to_string()],
+                ExprKind::Mult(
+                    Box::new(ExprKind::Var("w".to_string()).into()),
+                    Box::new(ExprKind::Var("h".to_string()).into())
+                )
+                .into()
+            ),
+            Stmt::Fn("one".to_string(), vec![], ExprKind::num(1.0).into()),
+        ]
+    );
+    assert_eq!(
+        program.result.unwrap(),
+        ExprKind::Call(
+            "area".to_string(),
+            vec![
+                ExprKind::Call("one".to_string(), vec![]).into(),
+                ExprKind::num(2.0).into()
+            ]
+        )
+        .into()
+    );
+}
+
+#[test]
+fn test_parse_duplicate_parameter() {
+    assert_eq!(
+        parse("fn f(a, b, a) = a; f(1, 2, 3)").unwrap_err(),
+        vec![ParseError::custom(
+            4..13,
+            "duplicate parameter `a`".to_string()
+        )]
+    );
+}
+
+#[test]
+fn test_parse_power_is_right_associative() {
+    let num = |x| Box::new(ExprKind::num(x).into());
+
+    assert_eq!(
+        parse("2 ^ 3 ^ 4").unwrap().result.unwrap(),
+        ExprKind::Pow(num(2.0), Box::new(ExprKind::Pow(num(3.0), num(4.0)).into())).into()
+    );
+    assert_eq!(
+        parse("-2 ^ ~3 % 4").unwrap().result.unwrap(),
+        ExprKind::Mod(
+            Box::new(
+                ExprKind::Negative(Box::new(
+                    ExprKind::Pow(num(2.0), Box::new(ExprKind::Invert(num(3.0)).into())).into()
+                ))
+                .into()
+            ),
+            num(4.0)
+        )
+        .into()
+    );
+}
+
+#[test]
+fn test_parse_logic_precedence() {
+    let var = |name: &str| Box::new(ExprKind::Var(name.to_string()).into());
+
+    assert_eq!(
+        parse("a || b && !c == d").unwrap().result.unwrap(),
+        ExprKind::Or(
+            var("a"),
+            Box::new(
+                ExprKind::And(
+                    var("b"),
+                    Box::new(
+                        ExprKind::Eq(Box::new(ExprKind::Not(var("c")).into()), var("d")).into()
+                    )
+                )
+                .into()
+            )
+        )
+        .into()
+    );
+    assert_eq!(
+        parse("1 + if a then b else c * 2").unwrap().result.unwrap(),
+        ExprKind::Add(
+            Box::new(ExprKind::num(1.0).into()),
+            Box::new(
+                ExprKind::If(
+                    var("a"),
+                    var("b"),
+                    Box::new(ExprKind::Mult(var("c"), Box::new(ExprKind::num(2.0).into())).into())
+                )
+                .into()
+            )
+        )
+        .into()
+    );
+}
+
+#[test]
+fn test_parse_comparisons_do_not_chain() {
+    assert!(parse("1 < 2 < 3").is_err());
+    assert!(parse("let if = 1; if").is_err());
+}
+
+#[test]
+fn test_parse_positions() {
+    let program = parse("let x = 1;\n\nx * (2 +\n  pi)").unwrap();
+    let product = program.result.as_ref().unwrap();
+    let sum = match &product.kind {
+        ExprKind::Mult(_, rhs) => rhs,
+        _ => unreachable!(),
+    };
+
+    let pi = match &sum.kind {
+        ExprKind::Add(_, rhs) => rhs,
+        _ => unreachable!(),
+    };
+
+    assert_eq!(product.position, Position { line: 3, column: 1 });
+    assert_eq!(sum.position, Position { line: 3, column: 5 });
+    assert_eq!(pi.position, Position { line: 4, column: 3 });
+    assert_eq!(pi.span, 23..25);
+}
+
+#[test]
+fn test_parse_errors_name_tokens() {
+    let message = |src| parse(src).unwrap_err()[0].message();
+
+    assert_eq!(
+        message("let if = 1; 2"),
+        "unexpected keyword `if`, expected name"
+    );
+    assert_eq!(
+        message("1 + 2 x"),
+        "unexpected name `x`, expected `!=`, `%`, `&&`, `*`, `+`, `-`, `/`, `<=`, `<`, `==`, `>=`, `>`, `^`, `||` or end of input"
+    );
+    assert_eq!(message("fn f(x) 1"), "unexpected number `1`, expected `=`");
+}
+
+#[test]
+fn test_parse_print_statements_and_comments() {
+    let program = parse("# totals\nlet x = 2; // two\nprint x; /* then\n the result */ x").unwrap();
+
+    assert_eq!(
+        program.stmts,
+        vec![
+            Stmt::Let("x".to_string(), ExprKind::num(2.0).into()),
+            Stmt::Print(ExprKind::Var("x".to_string()).into()),
+        ]
+    );
+    assert_eq!(program.result, Some(ExprKind::Var("x".to_string()).into()));
+    assert!(parse("print = 1; print").is_err());
+    assert!(parse("print 1 2").is_err());
+}
+
+#[test]
+fn test_parse_lambdas_and_lists() {
+    let program = parse("|a, b| a + [b, || 1]").unwrap();
+
+    assert_eq!(
+        program.result.unwrap().kind,
+        ExprKind::Lambda(
+            vec!["a".to_string(), "b".to_string()],
+            Box::new(
+                ExprKind::Add(
+                    Box::new(ExprKind::Var("a".to_string()).into()),
+                    Box::new(
+                        ExprKind::List(vec![
+                            ExprKind::Var("b".to_string()).into(),
+                            ExprKind::Lambda(vec![], Box::new(ExprKind::num(1.0).into())).into(),
+                        ])
+                        .into()
+                    ),
+                )
+                .into()
+            ),
+        )
+    );
+
+    let errs = parse("|x, x| x").unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(
+        Diagnostic::from(&errs[0]),
+        Diagnostic {
+            message: "duplicate parameter `x`".to_string(),
+            span: 0..6,
+        }
+    );
+    assert_eq!(parse("[1, 2").unwrap_err().len(), 1);
+}
+
+#[test]
+fn test_parse_units_are_written_without_spaces() {
+    let quantity = |src| match parse(src).unwrap().result.unwrap().kind {
+        ExprKind::Quantity(_, unit) => unit,
+        kind => panic!("{:?}", kind),
+    };
+
+    assert_eq!(
+        quantity("9.81 m/s^2"),
+        vec![("m".to_string(), 1), ("s".to_string(), -2)]
+    );
+    assert_eq!(
+        quantity("1 kg*m"),
+        vec![("kg".to_string(), 1), ("m".to_string(), 1)]
+    );
+    assert!(matches!(
+        parse("10 m / s").unwrap().result.unwrap().kind,
+        ExprKind::Div(_, _)
+    ));
+
+    let errs = parse("10 m /s").unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].span, 4..7);
+    assert_eq!(
+        errs[0].kind,
+        ErrorKind::Custom("a unit can't have a space before `/`".to_string())
+    );
+
+    let message = |src| parse(src).unwrap_err()[0].kind.clone();
+    assert_eq!(
+        message("2 m^2147483648"),
+        ErrorKind::Custom("the power of a unit is too large: `2147483648`".to_string())
+    );
+    assert_eq!(
+        message("2 m^1.5"),
+        ErrorKind::Custom("the power of a unit must be a whole number, not `1.5`".to_string())
+    );
+    assert_eq!(
+        message("2 m^-2147483648"),
+        ErrorKind::Custom("the power of a unit is too large: `2147483648`".to_string())
+    );
+}
@@ -0,0 +1,303 @@
+use crate::diagnostics;
+use crate::eval::{self, Env};
+use crate::lexer;
+use crate::parser::{self, ParseError};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+const HELP: &str = "\
+:ast <code>     show the parsed tree of <code>
+:tokens <code>  show the tokens of <code>
+:help           show this message
+:quit           exit the REPL
+
+Lines ending in the middle of an expression continue on the next line,
+an empty line forces the pending input to be evaluated.";
+
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Output(String),
+    Incomplete,
+    Nothing,
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Repl {
+    env: Env,
+    buffer: String,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            ">> "
+        } else {
+            ".. "
+        }
+    }
+
+    pub fn feed(&mut self, line: &str) -> Reply {
+        if self.buffer.is_empty() {
+            if let Some(command) = line.trim().strip_prefix(':') {
+                return self.command(command);
+            }
+        }
+
+        let force = line.trim().is_empty();
+
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+
+        let src = std::mem::take(&mut self.buffer);
+
+        match parser::parse_repl(&src) {
+            Err(errs) if !force && is_incomplete(&src, &errs) => {
+                self.buffer = src;
+                Reply::Incomplete
+            }
+            Err(errs) => Reply::Output(render_errors(&src, &errs)),
+            Ok((stmts, result)) => {
+                let mut env = self.env.clone();
+                let mut lines = Vec::new();
+                let mut print = |value: eval::Value| lines.push(value.to_string());
+
+                for stmt in &stmts {
+                    env = match eval::exec(stmt, &env, &mut print) {
+                        Ok(env) => env,
+                        Err(err) => {
+                            lines.push(diagnostics::render("<repl>", &src, &(&err).into()));
+                            return Reply::Output(lines.join("\n"));
+                        }
+                    };
+                }
+
+                match result.map(|expr| eval::eval(&expr, &env)) {
+                    None => (),
+                    Some(Ok(x)) => lines.push(x.to_string()),
+                    Some(Err(err)) => {
+                        lines.push(diagnostics::render("<repl>", &src, &(&err).into()));
+                        return Reply::Output(lines.join("\n"));
+                    }
+                }
+
+                self.env = env;
+
+                // Printed values come before the result, like they would when running a file.
+                if lines.is_empty() {
+                    Reply::Nothing
+                } else {
+                    Reply::Output(lines.join("\n"))
+                }
+            }
+        }
+    }
+
+    fn command(&self, command: &str) -> Reply {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+
+        match name {
+            "q" | "quit" => Reply::Quit,
+            "help" => Reply::Output(HELP.to_string()),
+            "ast" => match parser::parse_repl(arg) {
+                Ok((stmts, None)) => Reply::Output(format!("{:#?}", stmts)),
+                Ok((stmts, Some(expr))) if stmts.is_empty() => {
+                    Reply::Output(format!("{:#?}", expr))
+                }
+                Ok((stmts, Some(expr))) => Reply::Output(format!("{:#?}\n{:#?}", stmts, expr)),
+                Err(errs) => Reply::Output(render_errors(arg, &errs)),
+            },
+            "tokens" => match lexer::lex(arg) {
+                Ok(tokens) => Reply::Output(lexer::format_tokens(&tokens).trim_end().to_string()),
+                Err(errs) => Reply::Output(render_errors(arg, &errs)),
+            },
+            _ => Reply::Output(format!("unknown command `:{}`, try `:help`", name)),
+        }
+    }
+}
+
+// Input is incomplete when every error is about running out of input, like an open
+// parenthesis or a trailing operator.
+fn is_incomplete(src: &str, errs: &[ParseError]) -> bool {
+    let end = src.trim_end().len();
+
+    errs.iter()
+        .all(|err| err.found.is_none() && err.span.start >= end)
+}
+
+fn render_errors(src: &str, errs: &[ParseError]) -> String {
+    errs.iter()
+        .map(|err| diagnostics::render("<repl>", src, &err.into()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+pub fn run() -> rustyline::Result<()> {
+    let mut editor = Editor::<()>::new()?;
+    let mut repl = Repl::new();
+
+    loop {
+        let line = match editor.readline(repl.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str());
+        }
+
+        match repl.feed(&line) {
+            Reply::Output(output) => println!("{}", output),
+            Reply::Incomplete | Reply::Nothing => (),
+            Reply::Quit => return Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_repl_evaluates_expressions() {
+    let mut repl = Repl::new();
+
+    assert_eq!(repl.feed("1 + 2 * 3"), Reply::Output("7".to_string()));
+}
+
+#[test]
+fn test_repl_keeps_definitions_between_lines() {
+    let mut repl = Repl::new();
+
+    assert_eq!(repl.feed("let rate = 3;"), Reply::Nothing);
+    assert_eq!(
+        repl.feed("let n = 12; rate * n"),
+        Reply::Output("36".to_string())
+    );
+    assert_eq!(repl.feed("n"), Reply::Output("12".to_string()));
+}
+
+#[test]
+fn test_repl_print() {
+    let mut repl = Repl::new();
+
+    assert_eq!(
+        repl.feed("let x = 2; print x; print x > 1;"),
+        Reply::Output("2\ntrue".to_string())
+    );
+    assert_eq!(
+        repl.feed("print x * 3; x"),
+        Reply::Output("6\n2".to_string())
+    );
+}
+
+#[test]
+fn test_repl_failed_line_does_not_define_anything() {
+    let mut repl = Repl::new();
+
+    assert_eq!(
+        repl.feed("let x = 1; let y = 1 / 0;"),
+        Reply::Output(
+            concat!(
+                "error: division by zero\n",
+                " --> <repl>:1:20\n",
+                "  |\n",
+                "1 | let x = 1; let y = 1 / 0;\n",
+                "  |                    ^^^^^",
+            )
+            .to_string()
+        )
+    );
+    assert_eq!(
+        repl.feed("x"),
+        Reply::Output(
+            concat!(
+                "error: undefined variable `x`\n",
+                " --> <repl>:1:1\n",
+                "  |\n",
+                "1 | x\n",
+                "  | ^",
+            )
+            .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_repl_multi_line_input() {
+    let mut repl = Repl::new();
+
+    assert_eq!(repl.feed("(1 +"), Reply::Incomplete);
+    assert_eq!(repl.prompt(), ".. ");
+    assert_eq!(repl.feed("2) *"), Reply::Incomplete);
+    assert_eq!(repl.feed("3"), Reply::Output("9".to_string()));
+    assert_eq!(repl.prompt(), ">> ");
+}
+
+#[test]
+fn test_repl_empty_line_forces_evaluation() {
+    let mut repl = Repl::new();
+
+    assert_eq!(repl.feed("1 +"), Reply::Incomplete);
+
+    match repl.feed("") {
+        Reply::Output(output) => assert!(output.starts_with("error: unexpected end of input")),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    assert_eq!(repl.prompt(), ">> ");
+}
+
+#[test]
+fn test_repl_syntax_error() {
+    let mut repl = Repl::new();
+
+    assert_eq!(
+        repl.feed("1 + * 2"),
+        Reply::Output(
+            concat!(
+                "error: unexpected `*`, expected `!`, `(`, `-`, `~`, boolean, `if`, lambda, list, name or number\n",
+                " --> <repl>:1:5\n",
+                "  |\n",
+                "1 | 1 + * 2\n",
+                "  |     ^",
+            )
+            .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_repl_meta_commands() {
+    use crate::ast::{Expr, ExprKind, Position};
+
+    let mut repl = Repl::new();
+    let x = Expr {
+        kind: ExprKind::Var("x".to_string()),
+        span: 1..2,
+        position: Position { line: 1, column: 2 },
+    };
+
+    assert_eq!(
+        repl.feed(":ast -x"),
+        Reply::Output(format!(
+            "{:#?}",
+            Expr {
+                kind: ExprKind::Negative(Box::new(x)),
+                span: 0..2,
+                position: Position { line: 1, column: 1 },
+            }
+        ))
+    );
+    assert_eq!(
+        repl.feed(":tokens 1 + x"),
+        Reply::Output("0..1 number `1`\n2..3 `+`\n4..5 name `x`".to_string())
+    );
+    assert_eq!(
+        repl.feed(":nope"),
+        Reply::Output("unknown command `:nope`, try `:help`".to_string())
+    );
+    assert_eq!(repl.feed(":quit"), Reply::Quit);
+}
@@ -0,0 +1,376 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::eval::{self, Env, RuntimeError, Value};
+use crate::format::{format_expr, format_stmt};
+use serde_json::{json, Value as Json};
+use std::rc::Rc;
+
+// Evaluation one reduction at a time: each step replaces the leftmost sub-expression
+// whose operands are all values with its result. The evaluator does the reducing, so
+// results and errors are the same as running the program.
+
+pub struct Step {
+    pub reduced: Expr,
+    pub result: Expr,
+    pub expr: Expr,
+}
+
+pub struct Trace {
+    pub source: String,
+    pub steps: Vec<Step>,
+}
+
+// Names of closures are left alone, so they keep the scope they were created in when
+// they are called. A lambda is a value, and its body is only reduced when it's called.
+fn is_value(expr: &Expr, env: &Env) -> bool {
+    match &expr.kind {
+        ExprKind::Num(_, _) | ExprKind::Quantity(_, _) | ExprKind::Bool(_) => true,
+        ExprKind::Lambda(_, _) => true,
+        ExprKind::Var(name) => matches!(env.lookup(name), Some(Value::Closure(_))),
+        ExprKind::List(items) => items.iter().all(|item| is_value(item, env)),
+        _ => false,
+    }
+}
+
+// A closure can't be turned back into a lambda, since that would lose the scope it
+// captured. It's bound to a name that can't appear in source instead, and `show` puts
+// the lambda back when the step is printed.
+fn literal(value: Value, expr: &Expr, env: &mut Env) -> Expr {
+    let kind = match value {
+        Value::Num(x) => ExprKind::num(x),
+        Value::Quantity(quantity) => ExprKind::Quantity(quantity.value, quantity.unit),
+        Value::Bool(b) => ExprKind::Bool(b),
+        Value::List(items) => ExprKind::List(
+            items
+                .into_iter()
+                .map(|item| literal(item, expr, env))
+                .collect(),
+        ),
+        Value::Closure(function) => {
+            let name = format!("<closure {:p}>", Rc::as_ptr(&function));
+            *env = env.bind(&name, Value::Closure(function));
+            ExprKind::Var(name)
+        }
+    };
+
+    Expr {
+        kind,
+        span: expr.span.clone(),
+        position: expr.position,
+    }
+}
+
+fn show(expr: &Expr, env: &Env) -> Expr {
+    let mut expr = expr.clone();
+
+    if let ExprKind::Var(name) = &expr.kind {
+        if let (true, Some(Value::Closure(function))) = (name.starts_with('<'), env.lookup(name)) {
+            expr.kind = ExprKind::Lambda(function.params.clone(), Box::new(function.body.clone()));
+        }
+    }
+
+    for child in expr.kind.children_mut() {
+        *child = show(child, env);
+    }
+
+    expr
+}
+
+// Returns the sub-expression that was reduced and what it was reduced to, or `None`
+// when `expr` is already a value.
+fn reduce(expr: &mut Expr, env: &mut Env) -> Result<Option<(Expr, Expr)>, RuntimeError> {
+    if is_value(expr, env) {
+        return Ok(None);
+    }
+
+    // Only the condition of an `if` and the left side of `&&` and `||` are reduced
+    // before deciding, the rest is only reduced when it's needed.
+    let lazy = match &mut expr.kind {
+        ExprKind::If(cond, _, _) | ExprKind::And(cond, _) | ExprKind::Or(cond, _) => Some(cond),
+        _ => None,
+    };
+
+    match lazy {
+        Some(cond) if !is_value(cond, env) => return reduce(cond, env),
+        Some(_) => (),
+        None => {
+            if let Some(child) = expr
+                .kind
+                .children_mut()
+                .into_iter()
+                .find(|child| !is_value(child, &*env))
+            {
+                return reduce(child, env);
+            }
+        }
+    }
+
+    let branch = match &expr.kind {
+        ExprKind::If(cond, then, otherwise) => match cond.kind {
+            ExprKind::Bool(true) => Some(then),
+            ExprKind::Bool(false) => Some(otherwise),
+            _ => None,
+        },
+        ExprKind::And(lhs, rhs) => match lhs.kind {
+            ExprKind::Bool(true) => Some(rhs),
+            ExprKind::Bool(false) => Some(lhs),
+            _ => None,
+        },
+        ExprKind::Or(lhs, rhs) => match lhs.kind {
+            ExprKind::Bool(true) => Some(lhs),
+            ExprKind::Bool(false) => Some(rhs),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    // Anything else, including a condition of the wrong type, goes to the evaluator.
+    let result = match branch {
+        Some(branch) => (**branch).clone(),
+        None => literal(eval::eval(expr, env)?, expr, env),
+    };
+
+    let reduced = std::mem::replace(expr, result.clone());
+    Ok(Some((reduced, result)))
+}
+
+fn trace_expr(expr: &Expr, env: &Env, steps: &mut Vec<Step>) -> Result<Value, RuntimeError> {
+    let mut expr = expr.clone();
+    let mut env = env.clone();
+
+    while let Some((reduced, result)) = reduce(&mut expr, &mut env)? {
+        steps.push(Step {
+            reduced: show(&reduced, &env),
+            result: show(&result, &env),
+            expr: show(&expr, &env),
+        });
+    }
+
+    eval::eval(&expr, &env)
+}
+
+// Hands the trace of every statement and the result to `out`, including the steps
+// that were taken before an error.
+pub fn trace_program(
+    program: &Program,
+    env: &Env,
+    out: &mut dyn FnMut(Trace),
+) -> Result<Option<Value>, RuntimeError> {
+    let mut env = env.clone();
+
+    for stmt in &program.stmts {
+        let mut steps = Vec::new();
+        let source = format_stmt(stmt);
+
+        let value = match stmt {
+            Stmt::Let(_, value) | Stmt::Print(value) => trace_expr(value, &env, &mut steps),
+            Stmt::Fn(_, _, _) => Ok(Value::Bool(true)),
+        };
+
+        out(Trace { source, steps });
+
+        env = match (stmt, value?) {
+            (Stmt::Let(name, _), value) => env.bind(name, value),
+            (Stmt::Fn(_, _, _), _) => eval::exec(stmt, &env, &mut |_| ())?,
+            (Stmt::Print(_), _) => env,
+        };
+    }
+
+    let result = match &program.result {
+        Some(result) => result,
+        None => return Ok(None),
+    };
+    let mut steps = Vec::new();
+    let value = trace_expr(result, &env, &mut steps);
+
+    out(Trace {
+        source: format_expr(result),
+        steps,
+    });
+    value.map(Some)
+}
+
+pub fn format_trace(trace: &Trace) -> String {
+    let exprs = trace
+        .steps
+        .iter()
+        .map(|step| format_expr(&step.expr))
+        .collect::<Vec<_>>();
+    let width = exprs.iter().map(|expr| expr.chars().count()).max();
+    let mut out = format!("{}\n", trace.source);
+
+    for (step, expr) in trace.steps.iter().zip(&exprs) {
+        out += &format!(
+            "  → {:width$}    ({} = {})\n",
+            expr,
+            format_expr(&step.reduced),
+            format_expr(&step.result),
+            width = width.unwrap_or(0)
+        );
+    }
+
+    out
+}
+
+pub fn to_json(traces: &[Trace], result: &Result<Option<Value>, RuntimeError>) -> Json {
+    let traces = traces
+        .iter()
+        .map(|trace| {
+            let steps = trace
+                .steps
+                .iter()
+                .map(|step| {
+                    json!({
+                        "reduced": format_expr(&step.reduced),
+                        "span": [step.reduced.span.start, step.reduced.span.end],
+                        "result": format_expr(&step.result),
+                        "expr": format_expr(&step.expr),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            json!({"source": trace.source, "steps": steps})
+        })
+        .collect::<Vec<_>>();
+
+    match result {
+        Ok(value) => json!({
+            "traces": traces,
+            "result": value.as_ref().map(Value::to_string),
+        }),
+        Err(err) => json!({
+            "traces": traces,
+            "error": {
+                "message": err.error.to_string(),
+                "span": [err.span.start, err.span.end],
+            },
+        }),
+    }
+}
+
+#[cfg(test)]
+fn trace_str(src: &str) -> String {
+    let mut out = String::new();
+    let result = trace_program(
+        &crate::parser::parse(src).unwrap(),
+        &Env::new(),
+        &mut |trace| out += &format_trace(&trace),
+    );
+
+    match result {
+        Ok(Some(value)) => out + &format!("= {}", value),
+        Ok(None) => out,
+        Err(err) => out + &format!("error: {}", err.error),
+    }
+}
+
+#[test]
+fn test_trace_reduces_leftmost_first() {
+    assert_eq!(
+        trace_str("1 + ~4 - 2 / 8"),
+        concat!(
+            "1 + ~4 - 2 / 8\n",
+            "  → 1 + 0.25 - 2 / 8    (~4 = 0.25)\n",
+            "  → 1.25 - 2 / 8        (1 + 0.25 = 1.25)\n",
+            "  → 1.25 - 0.25         (2 / 8 = 0.25)\n",
+            "  → 1                   (1.25 - 0.25 = 1)\n",
+            "= 1",
+        )
+    );
+}
+
+#[test]
+fn test_trace_statements() {
+    assert_eq!(
+        trace_str("let rate = 2 * 3; fn f(x) = x + rate; print rate; f(1)"),
+        concat!(
+            "let rate = 2 * 3;\n",
+            "  → 6    (2 * 3 = 6)\n",
+            "fn f(x) = x + rate;\n",
+            "print rate;\n",
+            "  → 6    (rate = 6)\n",
+            "f(1)\n",
+            "  → 7    (f(1) = 7)\n",
+            "= 7",
+        )
+    );
+}
+
+#[test]
+fn test_trace_only_reduces_needed_operands() {
+    assert_eq!(
+        trace_str("if 1 > 2 || true then 1 else 1 / 0"),
+        concat!(
+            "if 1 > 2 || true then 1 else 1 / 0\n",
+            "  → if false || true then 1 else 1 / 0    (1 > 2 = false)\n",
+            "  → if true then 1 else 1 / 0             (false || true = true)\n",
+            "  → 1                                     (if true then 1 else 1 / 0 = 1)\n",
+            "= 1",
+        )
+    );
+    assert_eq!(
+        trace_str("false && 1 / 0 > 0"),
+        "false && 1 / 0 > 0\n  → false    (false && 1 / 0 > 0 = false)\n= false"
+    );
+}
+
+#[test]
+fn test_trace_stops_at_errors() {
+    let src = "let x = 4; (x - 1) / (x - 4)";
+    let mut traces = Vec::new();
+    let result = trace_program(
+        &crate::parser::parse(src).unwrap(),
+        &Env::new(),
+        &mut |trace| traces.push(trace),
+    );
+
+    assert_eq!(
+        format_trace(&traces[1]),
+        concat!(
+            "(x - 1) / (x - 4)\n",
+            "  → (4 - 1) / (x - 4)    (x = 4)\n",
+            "  → 3 / (x - 4)          (4 - 1 = 3)\n",
+            "  → 3 / (4 - 4)          (x = 4)\n",
+            "  → 3 / 0                (4 - 4 = 0)\n",
+        )
+    );
+
+    let json = to_json(&traces, &result);
+    assert_eq!(json["error"]["message"], "division by zero");
+    assert_eq!(json["error"]["span"], json!([11, 28]));
+    assert_eq!(
+        json["traces"][1]["steps"][1],
+        json!({"reduced": "4 - 1", "span": [11, 18], "result": "3", "expr": "3 / (x - 4)"})
+    );
+}
+
+#[test]
+fn test_trace_lambdas_and_lists() {
+    assert_eq!(
+        trace_str("let k = 2; map([1, k], |x| x * k)"),
+        concat!(
+            "let k = 2;\n",
+            "map([1, k], |x| x * k)\n",
+            "  → map([1, 2], |x| x * k)    (k = 2)\n",
+            "  → [2, 4]                    (map([1, 2], |x| x * k) = [2, 4])\n",
+            "= [2, 4]",
+        )
+    );
+}
+
+#[test]
+fn test_trace_keeps_the_scope_of_closures() {
+    let src = "fn mk(k) = |x| x * k; apply(mk(2), [3])";
+    let expected = concat!(
+        "fn mk(k) = |x| x * k;\n",
+        "apply(mk(2), [3])\n",
+        "  → apply(|x| x * k, [3])    (mk(2) = |x| x * k)\n",
+        "  → 6                        (apply(|x| x * k, [3]) = 6)\n",
+        "= 6",
+    );
+
+    assert_eq!(trace_str(src), expected);
+    assert_eq!(
+        trace_str(&format!("let k = 10; {}", src)),
+        format!("let k = 10;\n{}", expected)
+    );
+}
@@ -0,0 +1,321 @@
+use crate::ast::Unit;
+use crate::eval::EvalError;
+use std::fmt;
+use std::ops::{Div, Mul};
+
+// Powers of the SI base units metre, kilogram, second, ampere, kelvin and mole. They
+// are wider than unit powers so `km^2147483647` still has a dimension.
+type Dimension = [i64; 6];
+
+const NONE: Dimension = [0; 6];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0];
+
+struct UnitDef {
+    name: &'static str,
+    factor: f64,
+    dimension: Dimension,
+}
+
+const fn unit(name: &'static str, factor: f64, dimension: Dimension) -> UnitDef {
+    UnitDef {
+        name,
+        factor,
+        dimension,
+    }
+}
+
+// How many SI base units each unit is. Named derived units come last, and a product
+// of SI units with one of their dimensions is printed as the first of them that fits,
+// so `kg*m/s^2` is shown as `N`.
+const UNITS: [UnitDef; 25] = [
+    unit("m", 1.0, LENGTH),
+    unit("km", 1e3, LENGTH),
+    unit("cm", 1e-2, LENGTH),
+    unit("mm", 1e-3, LENGTH),
+    unit("ft", 0.3048, LENGTH),
+    unit("mi", 1609.344, LENGTH),
+    unit("kg", 1.0, MASS),
+    unit("g", 1e-3, MASS),
+    unit("mg", 1e-6, MASS),
+    unit("lb", 0.45359237, MASS),
+    unit("s", 1.0, TIME),
+    unit("ms", 1e-3, TIME),
+    unit("min", 60.0, TIME),
+    unit("h", 3600.0, TIME),
+    unit("A", 1.0, [0, 0, 0, 1, 0, 0]),
+    unit("K", 1.0, [0, 0, 0, 0, 1, 0]),
+    unit("mol", 1.0, [0, 0, 0, 0, 0, 1]),
+    unit("L", 1e-3, [3, 0, 0, 0, 0, 0]),
+    unit("Hz", 1.0, [0, 0, -1, 0, 0, 0]),
+    unit("N", 1.0, FORCE),
+    unit("J", 1.0, ENERGY),
+    unit("kJ", 1e3, ENERGY),
+    unit("W", 1.0, POWER),
+    unit("kW", 1e3, POWER),
+    unit("Pa", 1.0, [-1, 1, -2, 0, 0, 0]),
+];
+
+fn lookup(name: &str) -> Option<&'static UnitDef> {
+    UNITS.iter().find(|unit| unit.name == name)
+}
+
+pub fn is_unit(name: &str) -> bool {
+    lookup(name).is_some()
+}
+
+fn factor(unit: &Unit) -> f64 {
+    unit.iter()
+        .map(|(name, power)| lookup(name).map_or(1.0, |unit| unit.factor.powi(*power)))
+        .product()
+}
+
+fn dimension(unit: &Unit) -> Dimension {
+    let mut total = NONE;
+
+    for (name, power) in unit {
+        if let Some(unit) = lookup(name) {
+            for (total, d) in total.iter_mut().zip(unit.dimension) {
+                *total += d * *power as i64;
+            }
+        }
+    }
+
+    total
+}
+
+// `kg*m/s^2`, with a negative power when nothing is left to divide, like `s^-1`. This
+// is the syntax units are written in, so printed quantities can be read back.
+pub fn format_unit(unit: &Unit) -> String {
+    let part = |name: &str, power: i32| match power {
+        1 => name.to_string(),
+        _ => format!("{}^{}", name, power),
+    };
+    let mut out = unit
+        .iter()
+        .filter(|(_, power)| *power > 0)
+        .map(|(name, power)| part(name, *power))
+        .collect::<Vec<_>>()
+        .join("*");
+
+    for (name, power) in unit.iter().filter(|(_, power)| *power < 0) {
+        out = match out.is_empty() {
+            true => part(name, *power),
+            false => format!("{}/{}", out, part(name, -power)),
+        };
+    }
+
+    out
+}
+
+// A number with a unit, in that unit, so `5 km` stays `5 km` instead of `5000 m`. A
+// plain number is a quantity without a unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.value, format_unit(&self.unit))
+    }
+}
+
+// Powers of units stay within `i32`, and are symmetric so they can be inverted.
+fn checked_power(name: &str, power: i64) -> Result<i32, EvalError> {
+    match i32::try_from(power) {
+        Ok(power) if power != i32::MIN => Ok(power),
+        _ => Err(EvalError::Units(format!(
+            "the power of `{}` is too large",
+            name
+        ))),
+    }
+}
+
+fn describe(unit: &Unit) -> String {
+    match unit.is_empty() {
+        true => "a plain number".to_string(),
+        false => format!("`{}`", format_unit(unit)),
+    }
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: Unit) -> Result<Self, EvalError> {
+        if let Some((name, _)) = unit.iter().find(|(name, _)| !is_unit(name)) {
+            return Err(EvalError::Units(format!("unknown unit `{}`", name)));
+        }
+
+        Quantity { value, unit }.simplify()
+    }
+
+    pub fn plain(value: f64) -> Self {
+        Quantity {
+            value,
+            unit: Vec::new(),
+        }
+    }
+
+    // Powers of the same unit are merged and units that cancel out are dropped. A
+    // quantity without dimensions becomes a plain number, so `km/m` is 1000, and a
+    // product of SI units gets a name when it has one.
+    fn simplify(mut self) -> Result<Self, EvalError> {
+        let mut unit: Unit = Vec::new();
+
+        for (name, power) in self.unit {
+            match unit.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total = checked_power(&name, *total as i64 + power as i64)?,
+                None => unit.push((name, power)),
+            }
+        }
+
+        unit.retain(|(_, power)| *power != 0);
+
+        let dimension = dimension(&unit);
+
+        if dimension == NONE {
+            return Ok(Quantity::plain(self.value * factor(&unit)));
+        }
+
+        if unit.len() > 1 && factor(&unit) == 1.0 {
+            if let Some(named) = UNITS
+                .iter()
+                .find(|named| named.factor == 1.0 && named.dimension == dimension)
+            {
+                unit = vec![(named.name.to_string(), 1)];
+            }
+        }
+
+        self.unit = unit;
+        Ok(self)
+    }
+
+    // `other`'s value in this quantity's unit.
+    fn convert(&self, other: &Quantity, op: &str) -> Result<f64, EvalError> {
+        if dimension(&self.unit) != dimension(&other.unit) {
+            return Err(EvalError::Units(format!(
+                "cannot {} {} and {}",
+                op,
+                describe(&self.unit),
+                describe(&other.unit)
+            )));
+        }
+
+        Ok(other.value * (factor(&other.unit) / factor(&self.unit)))
+    }
+
+    // Both values in the unit of `self`, for operators that need compatible units.
+    pub fn align(&self, other: &Quantity, op: &str) -> Result<(f64, f64), EvalError> {
+        Ok((self.value, self.convert(other, op)?))
+    }
+
+    pub fn with_value(&self, value: f64) -> Self {
+        Quantity {
+            value,
+            unit: self.unit.clone(),
+        }
+    }
+
+    // Units of the same dimension are converted to the one on the left before they
+    // are combined, so `km * m` is in `km^2`.
+    fn combine(mut value: f64, mut unit: Unit, other: Unit) -> Result<Self, EvalError> {
+        for (name, power) in other {
+            let def = lookup(&name);
+            let same = unit
+                .iter()
+                .map(|(n, _)| n)
+                .find(|n| **n != name && lookup(n).map(|u| u.dimension) == def.map(|u| u.dimension))
+                .cloned();
+
+            match (same, def) {
+                (Some(same), Some(def)) => {
+                    value *= (def.factor / lookup(&same).map_or(1.0, |u| u.factor)).powi(power);
+                    unit.push((same, power));
+                }
+                _ => unit.push((name, power)),
+            }
+        }
+
+        Quantity { value, unit }.simplify()
+    }
+
+    // Only powers that leave whole powers of every unit are allowed, so `(4 m^2) ^ 0.5`
+    // is `2 m` but `(4 m) ^ 0.5` is an error.
+    pub fn pow(self, exponent: f64) -> Result<Self, EvalError> {
+        let mut unit = Vec::new();
+
+        for (name, power) in &self.unit {
+            let scaled = *power as f64 * exponent;
+
+            if scaled.fract() != 0.0 {
+                return Err(EvalError::Units(format!(
+                    "cannot raise {} to the power {}",
+                    describe(&self.unit),
+                    exponent
+                )));
+            }
+
+            unit.push((name.clone(), checked_power(name, scaled as i64)?));
+        }
+
+        Quantity {
+            value: self.value.powf(exponent),
+            unit,
+        }
+        .simplify()
+    }
+
+    // Built-ins that make sense for quantities keep their unit. The others, like `sin`,
+    // need a plain number.
+    pub fn apply(name: &str, args: Vec<Quantity>) -> Result<Quantity, EvalError> {
+        let first = &args[0];
+
+        match name {
+            "abs" => Ok(first.with_value(first.value.abs())),
+            "floor" => Ok(first.with_value(first.value.floor())),
+            "ceil" => Ok(first.with_value(first.value.ceil())),
+            "round" => Ok(first.with_value(first.value.round())),
+            "sqrt" => args[0].clone().pow(0.5),
+            "min" | "max" => {
+                let (a, b) = first.align(&args[1], &format!("take the {} of", name))?;
+                let pick = if name == "min" { a.min(b) } else { a.max(b) };
+                Ok(first.with_value(pick))
+            }
+            _ => {
+                let unit = args.iter().find(|arg| !arg.unit.is_empty()).unwrap();
+                Err(EvalError::Units(format!(
+                    "`{}` takes plain numbers but was given {}",
+                    name,
+                    describe(&unit.unit)
+                )))
+            }
+        }
+    }
+}
+
+impl Mul for Quantity {
+    type Output = Result<Quantity, EvalError>;
+
+    fn mul(self, other: Quantity) -> Self::Output {
+        Quantity::combine(self.value * other.value, self.unit, other.unit)
+    }
+}
+
+// The caller checks for division by zero.
+impl Div for Quantity {
+    type Output = Result<Quantity, EvalError>;
+
+    fn div(self, other: Quantity) -> Self::Output {
+        let inverse = other
+            .unit
+            .into_iter()
+            .map(|(name, power)| (name, -power))
+            .collect();
+
+        Quantity::combine(self.value / other.value, self.unit, inverse)
+    }
+}
@@ -0,0 +1,907 @@
+use crate::ast::{Expr, ExprKind, Program, Stmt};
+use crate::builtins::{self, BUILTINS};
+use crate::eval::{check_arity, divide, equal, remainder, Env, EvalError, Value, MAX_CALL_DEPTH};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Opcode {
+    Const,
+    Load,
+    Store,
+    LoadGlobal,
+    Call,
+    CallBuiltin,
+    Neg,
+    Inv,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    True,
+    False,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Jump,
+    JumpIfFalse,
+    JumpIfTrue,
+    Return,
+    Print,
+}
+
+const OPCODES: [Opcode; 28] = [
+    Opcode::Const,
+    Opcode::Load,
+    Opcode::Store,
+    Opcode::LoadGlobal,
+    Opcode::Call,
+    Opcode::CallBuiltin,
+    Opcode::Neg,
+    Opcode::Inv,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::Mod,
+    Opcode::Pow,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Not,
+    Opcode::Eq,
+    Opcode::Ne,
+    Opcode::Lt,
+    Opcode::Le,
+    Opcode::Gt,
+    Opcode::Ge,
+    Opcode::Jump,
+    Opcode::JumpIfFalse,
+    Opcode::JumpIfTrue,
+    Opcode::Return,
+    Opcode::Print,
+];
+
+impl Opcode {
+    // Most operands are a little endian `u16`, calls also have a `u8` argument count.
+    // Jumps are relative to the next instruction and only go forward, so function bodies
+    // can be moved without patching them.
+    fn operands(self) -> usize {
+        match self {
+            Opcode::Const
+            | Opcode::Load
+            | Opcode::Store
+            | Opcode::LoadGlobal
+            | Opcode::Jump
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfTrue => 2,
+            Opcode::Call | Opcode::CallBuiltin => 3,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    TooManyConstants,
+    TooManySlots,
+    TooManyFunctions,
+    TooManyArguments(String),
+    JumpTooFar,
+    UndefinedFunction(String),
+    NotAValue(String),
+    NotAFunction(String),
+    Units,
+    Lambdas,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::TooManyConstants => write!(f, "more than {} constants", u16::MAX),
+            CompileError::TooManySlots => write!(f, "more than {} variables", u16::MAX),
+            CompileError::TooManyFunctions => write!(f, "more than {} functions", u16::MAX),
+            CompileError::TooManyArguments(name) => {
+                write!(f, "call to `{}` has more than {} arguments", name, u8::MAX)
+            }
+            CompileError::JumpTooFar => write!(f, "branch longer than {} bytes", u16::MAX),
+            CompileError::UndefinedFunction(name) => {
+                write!(f, "undefined function `{}`", name)?;
+
+                match builtins::suggest(name) {
+                    Some(builtin) => write!(f, ", did you mean `{}`?", builtin),
+                    None => Ok(()),
+                }
+            }
+            CompileError::NotAValue(name) => {
+                write!(f, "`{}` is a function and must be called", name)
+            }
+            CompileError::NotAFunction(name) => write!(f, "`{}` is not a function", name),
+            CompileError::Units => write!(f, "units are not supported by the bytecode VM"),
+            CompileError::Lambdas => {
+                write!(f, "lambdas and lists are not supported by the bytecode VM")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub entry: usize,
+}
+
+// Variables that are never bound by a `let` become inputs, so one chunk can be run many
+// times with different values for them. Top level bindings and inputs live in global
+// slots, function parameters in slots relative to the current call frame.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<f64>,
+    pub inputs: Vec<(String, u16)>,
+    pub slot_names: Vec<String>,
+    pub functions: Vec<Function>,
+    pub max_stack: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Resolved {
+    Global(u16),
+    Local(u16),
+    Function(u16),
+    Constant(f64),
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    code: Vec<u8>,
+    bodies: Vec<Vec<u8>>,
+    scope: Vec<(String, Resolved)>,
+    in_function: bool,
+    stack: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Opcode, operand: Option<u16>) {
+        self.code.push(op as u8);
+
+        if let Some(operand) = operand {
+            self.code.extend(operand.to_le_bytes());
+        }
+
+        match op {
+            Opcode::Const | Opcode::Load | Opcode::LoadGlobal | Opcode::True | Opcode::False => {
+                self.stack += 1
+            }
+            Opcode::Store
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Mod
+            | Opcode::Pow
+            | Opcode::Eq
+            | Opcode::Ne
+            | Opcode::Lt
+            | Opcode::Le
+            | Opcode::Gt
+            | Opcode::Ge
+            | Opcode::JumpIfFalse
+            | Opcode::JumpIfTrue
+            | Opcode::Print => self.stack -= 1,
+            _ => (),
+        }
+
+        self.chunk.max_stack = self.chunk.max_stack.max(self.stack);
+    }
+
+    // Emits a jump with a placeholder distance and returns where to `patch` it.
+    fn jump(&mut self, op: Opcode) -> usize {
+        self.emit(op, Some(0));
+        self.code.len() - 2
+    }
+
+    fn patch(&mut self, at: usize) -> Result<(), CompileError> {
+        let distance =
+            u16::try_from(self.code.len() - at - 2).map_err(|_| CompileError::JumpTooFar)?;
+
+        self.code[at..at + 2].copy_from_slice(&distance.to_le_bytes());
+        Ok(())
+    }
+
+    // `a && b` and `a || b` check the type of `b` before producing a boolean, so they
+    // behave like `if a then b else false` with `b` required to be a boolean.
+    fn short_circuit(&mut self, skip: Opcode, lhs: &Expr, rhs: &Expr) -> Result<(), CompileError> {
+        let (done, result) = match skip {
+            Opcode::JumpIfFalse => (Opcode::True, Opcode::False),
+            _ => (Opcode::False, Opcode::True),
+        };
+
+        self.expr(lhs)?;
+        let skip_lhs = self.jump(skip);
+        self.expr(rhs)?;
+        let skip_rhs = self.jump(skip);
+        self.emit(done, None);
+        let end = self.jump(Opcode::Jump);
+
+        self.patch(skip_lhs)?;
+        self.patch(skip_rhs)?;
+        self.stack -= 1;
+        self.emit(result, None);
+        self.patch(end)
+    }
+
+    fn constant(&mut self, x: f64) -> Result<u16, CompileError> {
+        let constants = &mut self.chunk.constants;
+
+        let index = match constants.iter().position(|c| c.to_bits() == x.to_bits()) {
+            Some(index) => index,
+            None => {
+                constants.push(x);
+                constants.len() - 1
+            }
+        };
+
+        u16::try_from(index).map_err(|_| CompileError::TooManyConstants)
+    }
+
+    fn new_slot(&mut self, name: &str) -> Result<u16, CompileError> {
+        self.chunk.slot_names.push(name.to_string());
+
+        u16::try_from(self.chunk.slot_names.len() - 1).map_err(|_| CompileError::TooManySlots)
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Resolved, CompileError> {
+        if let Some((_, resolved)) = self.scope.iter().rev().find(|(n, _)| n == name) {
+            return Ok(*resolved);
+        }
+
+        if let Some(x) = builtins::constant(name) {
+            return Ok(Resolved::Constant(x));
+        }
+
+        if let Some((_, slot)) = self.chunk.inputs.iter().find(|(n, _)| n == name) {
+            return Ok(Resolved::Global(*slot));
+        }
+
+        let slot = self.new_slot(name)?;
+        self.chunk.inputs.push((name.to_string(), slot));
+        Ok(Resolved::Global(slot))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt {
+            Stmt::Let(name, value) => {
+                self.expr(value)?;
+
+                let slot = self.new_slot(name)?;
+                self.emit(Opcode::Store, Some(slot));
+                self.scope.push((name.clone(), Resolved::Global(slot)));
+            }
+            Stmt::Fn(name, params, body) => {
+                let index = u16::try_from(self.chunk.functions.len())
+                    .map_err(|_| CompileError::TooManyFunctions)?;
+
+                self.chunk.functions.push(Function {
+                    name: name.clone(),
+                    params: params.clone(),
+                    entry: 0,
+                });
+
+                let outer = self.scope.len();
+                let stack = std::mem::replace(&mut self.stack, 0);
+                let code = std::mem::take(&mut self.code);
+
+                self.scope.push((name.clone(), Resolved::Function(index)));
+
+                for (i, param) in params.iter().enumerate() {
+                    let slot = u16::try_from(i).map_err(|_| CompileError::TooManySlots)?;
+                    self.scope.push((param.clone(), Resolved::Local(slot)));
+                }
+
+                self.in_function = true;
+                self.expr(body)?;
+                self.emit(Opcode::Return, None);
+                self.in_function = false;
+
+                let body = std::mem::replace(&mut self.code, code);
+                self.bodies.push(body);
+                self.stack = stack;
+                self.scope.truncate(outer);
+                self.scope.push((name.clone(), Resolved::Function(index)));
+            }
+            Stmt::Print(value) => {
+                self.expr(value)?;
+                self.emit(Opcode::Print, None);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        let (op, lhs, rhs) = match &expr.kind {
+            ExprKind::Quantity(_, _) => return Err(CompileError::Units),
+            ExprKind::Lambda(_, _) | ExprKind::List(_) => return Err(CompileError::Lambdas),
+            ExprKind::Num(x, _) => {
+                let index = self.constant(*x)?;
+                self.emit(Opcode::Const, Some(index));
+                return Ok(());
+            }
+            ExprKind::Var(name) => {
+                match self.resolve(name)? {
+                    Resolved::Global(slot) if self.in_function => {
+                        self.emit(Opcode::LoadGlobal, Some(slot))
+                    }
+                    Resolved::Global(slot) | Resolved::Local(slot) => {
+                        self.emit(Opcode::Load, Some(slot))
+                    }
+                    Resolved::Constant(x) => return self.expr(&ExprKind::num(x).into()),
+                    Resolved::Function(_) => return Err(CompileError::NotAValue(name.clone())),
+                }
+
+                return Ok(());
+            }
+            ExprKind::Call(name, args) => {
+                let (op, index) = match self.scope.iter().rev().find(|(n, _)| n == name) {
+                    Some((_, Resolved::Function(index))) => (Opcode::Call, *index),
+                    Some(_) => return Err(CompileError::NotAFunction(name.clone())),
+                    None if builtins::higher_order(name).is_some() => {
+                        return Err(CompileError::Lambdas)
+                    }
+                    None => match builtins::builtin(name) {
+                        Some((index, _)) => (Opcode::CallBuiltin, index as u16),
+                        None => return Err(CompileError::UndefinedFunction(name.clone())),
+                    },
+                };
+
+                let argc = u8::try_from(args.len())
+                    .map_err(|_| CompileError::TooManyArguments(name.clone()))?;
+
+                for arg in args {
+                    self.expr(arg)?;
+                }
+
+                self.emit(op, Some(index));
+                self.code.push(argc);
+                self.stack = self.stack + 1 - args.len();
+                return Ok(());
+            }
+            ExprKind::Negative(x) => {
+                self.expr(x)?;
+                self.emit(Opcode::Neg, None);
+                return Ok(());
+            }
+            ExprKind::Invert(x) => {
+                self.expr(x)?;
+                self.emit(Opcode::Inv, None);
+                return Ok(());
+            }
+            ExprKind::Bool(b) => {
+                self.emit(if *b { Opcode::True } else { Opcode::False }, None);
+                return Ok(());
+            }
+            ExprKind::Not(x) => {
+                self.expr(x)?;
+                self.emit(Opcode::Not, None);
+                return Ok(());
+            }
+            ExprKind::And(lhs, rhs) => return self.short_circuit(Opcode::JumpIfFalse, lhs, rhs),
+            ExprKind::Or(lhs, rhs) => return self.short_circuit(Opcode::JumpIfTrue, lhs, rhs),
+            ExprKind::If(cond, then, otherwise) => {
+                self.expr(cond)?;
+                let skip_then = self.jump(Opcode::JumpIfFalse);
+                self.expr(then)?;
+                let end = self.jump(Opcode::Jump);
+
+                self.patch(skip_then)?;
+                self.stack -= 1;
+                self.expr(otherwise)?;
+                return self.patch(end);
+            }
+
+            ExprKind::Add(lhs, rhs) => (Opcode::Add, lhs, rhs),
+            ExprKind::Sub(lhs, rhs) => (Opcode::Sub, lhs, rhs),
+            ExprKind::Mult(lhs, rhs) => (Opcode::Mul, lhs, rhs),
+            ExprKind::Div(lhs, rhs) => (Opcode::Div, lhs, rhs),
+            ExprKind::Mod(lhs, rhs) => (Opcode::Mod, lhs, rhs),
+            ExprKind::Pow(lhs, rhs) => (Opcode::Pow, lhs, rhs),
+            ExprKind::Eq(lhs, rhs) => (Opcode::Eq, lhs, rhs),
+            ExprKind::NotEq(lhs, rhs) => (Opcode::Ne, lhs, rhs),
+            ExprKind::Less(lhs, rhs) => (Opcode::Lt, lhs, rhs),
+            ExprKind::LessEq(lhs, rhs) => (Opcode::Le, lhs, rhs),
+            ExprKind::Greater(lhs, rhs) => (Opcode::Gt, lhs, rhs),
+            ExprKind::GreaterEq(lhs, rhs) => (Opcode::Ge, lhs, rhs),
+        };
+
+        self.expr(lhs)?;
+        self.expr(rhs)?;
+        self.emit(op, None);
+        Ok(())
+    }
+}
+
+// Function bodies are laid out after the top level code, in definition order.
+pub fn compile(program: &Program) -> Result<Chunk, CompileError> {
+    let mut compiler = Compiler::default();
+
+    for stmt in &program.stmts {
+        compiler.stmt(stmt)?;
+    }
+
+    if let Some(result) = &program.result {
+        compiler.expr(result)?;
+    }
+
+    compiler.emit(Opcode::Return, None);
+
+    let mut chunk = compiler.chunk;
+    chunk.code = compiler.code;
+
+    for (function, body) in chunk.functions.iter_mut().zip(compiler.bodies) {
+        function.entry = chunk.code.len();
+        chunk.code.extend(body);
+    }
+
+    Ok(chunk)
+}
+
+struct Frame {
+    return_ip: usize,
+    base: usize,
+}
+
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+    slots: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `inputs` are given in the same order as `chunk.inputs`, printed values go to `out`.
+    // A missing input is reported like an undefined variable.
+    pub fn run(
+        &mut self,
+        chunk: &Chunk,
+        inputs: &[Value],
+        out: &mut dyn FnMut(Value),
+    ) -> Result<Option<Value>, EvalError> {
+        if let Some((name, _)) = chunk.inputs.get(inputs.len()) {
+            return Err(EvalError::UndefinedVariable(name.clone()));
+        }
+        check_arity("program", chunk.inputs.len(), inputs.len())?;
+
+        self.stack.clear();
+        self.stack.reserve(chunk.max_stack);
+        self.frames.clear();
+        self.slots.clear();
+        self.slots.resize(chunk.slot_names.len(), Value::Num(0.0));
+
+        for ((_, slot), value) in chunk.inputs.iter().zip(inputs) {
+            self.slots[*slot as usize] = value.clone();
+        }
+
+        let code = &chunk.code;
+        let mut ip = 0;
+        let mut base = 0;
+
+        loop {
+            let op = OPCODES[code[ip] as usize];
+            let operand = || u16::from_le_bytes([code[ip + 1], code[ip + 2]]) as usize;
+
+            match op {
+                Opcode::Const => self.stack.push(Value::Num(chunk.constants[operand()])),
+                Opcode::Load => self.stack.push(self.slots[base + operand()].clone()),
+                Opcode::Store => self.slots[base + operand()] = self.pop(),
+                Opcode::LoadGlobal => self.stack.push(self.slots[operand()].clone()),
+                Opcode::Call => {
+                    let function = &chunk.functions[operand()];
+                    let argc = code[ip + 3] as usize;
+
+                    check_arity(&function.name, function.params.len(), argc)?;
+
+                    if self.frames.len() >= MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit(function.name.clone()));
+                    }
+
+                    self.frames.push(Frame {
+                        return_ip: ip + 1 + op.operands(),
+                        base,
+                    });
+
+                    base = self.slots.len();
+                    self.slots
+                        .extend(self.stack.drain(self.stack.len() - argc..));
+
+                    ip = function.entry;
+                    continue;
+                }
+                Opcode::CallBuiltin => {
+                    let builtin = &BUILTINS[operand()];
+                    let argc = code[ip + 3] as usize;
+
+                    check_arity(builtin.name, builtin.arity, argc)?;
+
+                    let args = self
+                        .stack
+                        .split_off(self.stack.len() - argc)
+                        .into_iter()
+                        .map(Value::num)
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    self.stack.push(Value::Num(builtin.apply(&args)));
+                }
+                Opcode::Neg => {
+                    let x = self.pop().num()?;
+                    self.stack.push(Value::Num(-x));
+                }
+                Opcode::Inv => {
+                    let x = self.pop().num()?;
+                    self.stack.push(Value::Num(divide(1.0, x)?));
+                }
+                Opcode::Not => {
+                    let b = self.pop().bool()?;
+                    self.stack.push(Value::Bool(!b));
+                }
+                Opcode::True => self.stack.push(Value::Bool(true)),
+                Opcode::False => self.stack.push(Value::Bool(false)),
+                Opcode::Eq | Opcode::Ne => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+
+                    self.stack
+                        .push(Value::Bool(equal(lhs, rhs)? == (op == Opcode::Eq)));
+                }
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Mod
+                | Opcode::Pow
+                | Opcode::Lt
+                | Opcode::Le
+                | Opcode::Gt
+                | Opcode::Ge => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let (lhs, rhs) = (lhs.num()?, rhs.num()?);
+
+                    self.stack.push(match op {
+                        Opcode::Add => Value::Num(lhs + rhs),
+                        Opcode::Sub => Value::Num(lhs - rhs),
+                        Opcode::Mul => Value::Num(lhs * rhs),
+                        Opcode::Div => Value::Num(divide(lhs, rhs)?),
+                        Opcode::Mod => Value::Num(remainder(lhs, rhs)?),
+                        Opcode::Pow => Value::Num(lhs.powf(rhs)),
+                        Opcode::Lt => Value::Bool(lhs < rhs),
+                        Opcode::Le => Value::Bool(lhs <= rhs),
+                        Opcode::Gt => Value::Bool(lhs > rhs),
+                        _ => Value::Bool(lhs >= rhs),
+                    });
+                }
+                Opcode::Jump => {
+                    ip += 3 + operand();
+                    continue;
+                }
+                Opcode::JumpIfFalse | Opcode::JumpIfTrue => {
+                    if self.pop().bool()? == (op == Opcode::JumpIfTrue) {
+                        ip += 3 + operand();
+                        continue;
+                    }
+                }
+                Opcode::Print => out(self.pop()),
+                Opcode::Return => match self.frames.pop() {
+                    // Statements leave nothing behind, so the stack only holds the
+                    // result if the program has one.
+                    None => return Ok(self.stack.pop()),
+                    Some(frame) => {
+                        self.slots.truncate(base);
+                        base = frame.base;
+                        ip = frame.return_ip;
+                        continue;
+                    }
+                },
+            }
+
+            ip += 1 + op.operands();
+        }
+    }
+
+    pub fn run_with_env(
+        &mut self,
+        chunk: &Chunk,
+        env: &Env,
+        out: &mut dyn FnMut(Value),
+    ) -> Result<Option<Value>, EvalError> {
+        let inputs = chunk
+            .inputs
+            .iter()
+            .map(|(name, _)| {
+                env.lookup(name)
+                    .ok_or_else(|| EvalError::UndefinedVariable(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.run(chunk, &inputs, out)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow in compiled chunk")
+    }
+}
+
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut out = String::new();
+
+    if !chunk.inputs.is_empty() {
+        let inputs = chunk
+            .inputs
+            .iter()
+            .map(|(name, slot)| format!("{}@{}", name, slot));
+
+        out += &format!("inputs: {}\n", inputs.collect::<Vec<_>>().join(", "));
+    }
+
+    let width = OPCODES
+        .iter()
+        .map(|op| format!("{:?}", op).len())
+        .max()
+        .unwrap_or(0);
+    let mut ip = 0;
+    let mut function: Option<&Function> = None;
+
+    while ip < chunk.code.len() {
+        if let Some(f) = chunk.functions.iter().find(|f| f.entry == ip) {
+            out += &format!("\nfn {}({}):\n", f.name, f.params.join(", "));
+            function = Some(f);
+        }
+
+        let op = OPCODES[chunk.code[ip] as usize];
+        let name = format!("{:?}", op).to_uppercase();
+
+        if op.operands() == 0 {
+            out += &format!("{:04}  {}\n", ip, name);
+        } else {
+            let operand = u16::from_le_bytes([chunk.code[ip + 1], chunk.code[ip + 2]]) as usize;
+
+            let (operand, comment) = match (op, function) {
+                (Opcode::Const, _) => (operand.to_string(), chunk.constants[operand].to_string()),
+                (Opcode::Call, _) => (
+                    format!("{} {}", operand, chunk.code[ip + 3]),
+                    chunk.functions[operand].name.clone(),
+                ),
+                (Opcode::Jump | Opcode::JumpIfFalse | Opcode::JumpIfTrue, _) => {
+                    (operand.to_string(), format!("to {:04}", ip + 3 + operand))
+                }
+                (Opcode::CallBuiltin, _) => (
+                    format!("{} {}", operand, chunk.code[ip + 3]),
+                    BUILTINS[operand].name.to_string(),
+                ),
+                (Opcode::Load | Opcode::Store, Some(f)) => {
+                    (operand.to_string(), f.params[operand].clone())
+                }
+                _ => (operand.to_string(), chunk.slot_names[operand].clone()),
+            };
+
+            out += &format!(
+                "{:04}  {:<width$} {:<5} ; {}\n",
+                ip,
+                name,
+                operand,
+                comment,
+                width = width
+            );
+        }
+
+        ip += 1 + op.operands();
+    }
+
+    out
+}
+
+#[cfg(test)]
+fn compile_str(src: &str) -> Chunk {
+    compile(&crate::parser::parse(src).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+fn run_str(src: &str) -> Result<Option<Value>, EvalError> {
+    Vm::new().run_with_env(&compile_str(src), &Env::new(), &mut |_| {})
+}
+
+#[test]
+fn test_vm_matches_tree_walking() {
+    for src in [
+        "42",
+        "1 + ~1234 - 2 / 3",
+        "-(1 + 2) * ~4 - 3",
+        "let rate = 3; let n = 12; rate * n",
+        "let x = 2; let x = x * x; x + 1",
+        "1 / (2 - 2)",
+        "~0",
+        "y + 1",
+        "fn area(w, h) = w * h; area(3, 4) + area(1, 2)",
+        "let k = 2; fn f(x) = x * k; let k = 10; f(3) + k",
+        "let x = 100; fn f(x) = x + 1; f(1) + x",
+        "fn f(x) = f(x + 1); f(0)",
+        "fn f(a, b) = a + b; f(1)",
+        "2 ^ 3 ^ 2 % 5 - sqrt(x) * max(y, pi)",
+        "fn sqrt(x) = x; let e = 2; sqrt(e) + min(1, 2, 3)",
+        "7 % (x - x)",
+        "fn sq(x) = x * x; fn sum(a, b) = sq(a) + sq(b); sum(sq(2), 1 / 0)",
+        "1 < 2 && 2 <= 2 && !(3 > 4) && 3 >= 3 && 1 != 2 && true == true",
+        "false && 1 / 0 > 0 || true || 1 / 0 > 0",
+        "false || 1",
+        "true && 1",
+        "!1",
+        "1 == true",
+        "true + 1",
+        "true + 1 / 0",
+        "fn tax(x) = if x < 100 then 0 else if x < 1000 then x * 0.1 else x * 0.2; tax(50) + tax(500) + tax(5000)",
+        "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1); fact(10)",
+        "let big = if false then 1 else 2; -(if big > 1 then big else 0)",
+        "if 1 then 2 else 3",
+        "let x = 1; print x; print x + 1;",
+    ] {
+        let program = crate::parser::parse(src).unwrap();
+
+        assert_eq!(
+            run_str(src),
+            crate::eval::eval_program(&program, &Env::new(), &mut |_| {}).map_err(|err| err.error),
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_vm_runs_with_different_inputs() {
+    let chunk = compile_str("let k = 2; x * k + y");
+    let mut vm = Vm::new();
+
+    assert_eq!(
+        chunk
+            .inputs
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["x", "y"]
+    );
+    assert_eq!(
+        vm.run(&chunk, &[Value::Num(1.0), Value::Num(2.0)], &mut |_| {}),
+        Ok(Some(Value::Num(4.0)))
+    );
+    assert_eq!(
+        vm.run(&chunk, &[Value::Num(10.0), Value::Num(0.5)], &mut |_| {}),
+        Ok(Some(Value::Num(20.5)))
+    );
+    assert_eq!(
+        vm.run(&chunk, &[Value::Num(1.0)], &mut |_| {}),
+        Err(EvalError::UndefinedVariable("y".to_string()))
+    );
+    assert_eq!(
+        vm.run(&chunk, &vec![Value::Num(1.0); 3], &mut |_| {})
+            .unwrap_err()
+            .to_string(),
+        "`program` takes 2 argument(s) but 3 were given"
+    );
+}
+
+#[test]
+fn test_compile_deduplicates_constants_and_tracks_stack() {
+    let chunk = compile_str("1 + (2 * (1 + 2))");
+
+    assert_eq!(chunk.constants, vec![1.0, 2.0]);
+    assert_eq!(chunk.max_stack, 4);
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(
+        disassemble(&compile_str("let k = 0.5; -(x * k) + ~2")),
+        concat!(
+            "inputs: x@1\n",
+            "0000  CONST       0     ; 0.5\n",
+            "0003  STORE       0     ; k\n",
+            "0006  LOAD        1     ; x\n",
+            "0009  LOAD        0     ; k\n",
+            "0012  MUL\n",
+            "0013  NEG\n",
+            "0014  CONST       1     ; 2\n",
+            "0017  INV\n",
+            "0018  ADD\n",
+            "0019  RETURN\n",
+        )
+    );
+}
+
+#[test]
+fn test_compile_name_errors() {
+    let compile_err = |src| compile(&crate::parser::parse(src).unwrap()).unwrap_err();
+
+    assert_eq!(
+        compile_err("g(1)"),
+        CompileError::UndefinedFunction("g".to_string())
+    );
+    assert_eq!(
+        compile_err("let g = 1; g(1)"),
+        CompileError::NotAFunction("g".to_string())
+    );
+    assert_eq!(
+        compile_err("fn g() = 1; g + 1"),
+        CompileError::NotAValue("g".to_string())
+    );
+}
+
+#[test]
+fn test_disassemble_functions() {
+    assert_eq!(
+        disassemble(&compile_str("let k = 2; fn f(a) = a * k; f(3)")),
+        concat!(
+            "0000  CONST       0     ; 2\n",
+            "0003  STORE       0     ; k\n",
+            "0006  CONST       1     ; 3\n",
+            "0009  CALL        0 1   ; f\n",
+            "0013  RETURN\n",
+            "\n",
+            "fn f(a):\n",
+            "0014  LOAD        0     ; a\n",
+            "0017  LOADGLOBAL  0     ; k\n",
+            "0020  MUL\n",
+            "0021  RETURN\n",
+        )
+    );
+}
+
+#[test]
+fn test_disassemble_jumps() {
+    assert_eq!(
+        disassemble(&compile_str("if x > 1 then 2 else 3")),
+        concat!(
+            "inputs: x@0\n",
+            "0000  LOAD        0     ; x\n",
+            "0003  CONST       0     ; 1\n",
+            "0006  GT\n",
+            "0007  JUMPIFFALSE 6     ; to 0016\n",
+            "0010  CONST       1     ; 2\n",
+            "0013  JUMP        3     ; to 0019\n",
+            "0016  CONST       2     ; 3\n",
+            "0019  RETURN\n",
+        )
+    );
+}
+
+#[test]
+fn test_vm_print() {
+    let src = "let x = 2; print x; fn f(y) = y > x; print f(3); print 1 / 0; x";
+    let program = crate::parser::parse(src).unwrap();
+    let mut vm_printed = Vec::new();
+    let mut tree_printed = Vec::new();
+
+    assert_eq!(
+        Vm::new().run_with_env(&compile_str(src), &Env::new(), &mut |value| {
+            vm_printed.push(value)
+        }),
+        Err(EvalError::DivisionByZero)
+    );
+    assert!(
+        crate::eval::eval_program(&program, &Env::new(), &mut |value| {
+            tree_printed.push(value)
+        })
+        .is_err()
+    );
+    assert_eq!(vm_printed, vec![Value::Num(2.0), Value::Bool(true)]);
+    assert_eq!(vm_printed, tree_printed);
+}
@@ -0,0 +1,26 @@
+use std::process::{Command, Output};
+
+fn run(args: &[&str], src: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("ast-tree-cli-{}.x", std::process::id()));
+    std::fs::write(&path, src).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ast-tree"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn test_derive_by_a_free_variable() {
+    let output = run(&["--derive", "x"], "fn sq(y) = y * y; sq(x) + sin(x)");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "x + x + cos(x)\n"
+    );
+}